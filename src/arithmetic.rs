@@ -77,6 +77,15 @@ pub fn unsigned_to_signed(r: u16) -> i16 {
     v as i16
 }
 
+#[inline(always)]
+pub fn signed_to_unsigned(a: i16) -> u16 {
+    let mut r = a as u16;
+    if r & 0x8000 != 0 {
+        r ^= 0x7FFF;
+    }
+    r.rotate_left(1)
+}
+
 #[inline(always)]
 pub fn used_bits_safe(n: u64) -> usize {
     if n == 0 {
@@ -114,4 +123,15 @@ mod tests {
         assert_eq!(nth_set_bit_index(test_value, 3), 5);
         assert_eq!(nth_set_bit_index(test_value, 4), 7);
     }
+
+    #[test]
+    fn test_signed_unsigned_roundtrip() {
+        for v in i16::MIN..=i16::MAX {
+            assert_eq!(unsigned_to_signed(signed_to_unsigned(v)), v);
+        }
+
+        assert_eq!(signed_to_unsigned(0), 0);
+        assert_eq!(signed_to_unsigned(-1), 1);
+        assert_eq!(signed_to_unsigned(1), 2);
+    }
 }
@@ -9,6 +9,8 @@ pub enum BinpackError {
     InvalidMagic,
     #[error("Invalid format: {0}")]
     InvalidFormat(String),
+    #[error("Invalid entry: {0}")]
+    InvalidEntry(String),
 }
 
 pub type Result<T> = std::result::Result<T, BinpackError>;
@@ -47,6 +47,14 @@ impl std::ops::BitAnd for CastlingRights {
     }
 }
 
+impl std::ops::BitOr for CastlingRights {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
 pub struct CastlingTraits;
 
 impl CastlingTraits {
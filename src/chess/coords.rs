@@ -105,6 +105,17 @@ impl Square {
         })
     }
 
+    /// Parse a square in algebraic notation like `e4`
+    #[must_use]
+    pub fn parse(s: &str) -> Option<Self> {
+        match *s.as_bytes() {
+            [file @ b'a'..=b'h', rank @ b'1'..=b'8'] => {
+                Some(Self::new(((rank - b'1') * 8 + (file - b'a')) as u32))
+            }
+            _ => None,
+        }
+    }
+
     #[must_use]
     pub const fn is_valid(r: i64, f: i64) -> bool {
         r >= 0 && r < 8 && f >= 0 && f < 8
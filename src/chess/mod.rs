@@ -5,6 +5,7 @@ pub mod color;
 pub mod coords;
 mod hyperbola;
 pub mod r#move;
+pub mod movegen;
 pub mod piece;
 pub mod piecetype;
 pub mod position;
@@ -1,9 +1,11 @@
 use crate::chess::{
+    attacks::Attacks,
     castling_rights::CastleType,
     color::Color,
-    coords::{File, Square},
+    coords::{File, Rank, Square},
     piece::Piece,
     piecetype::PieceType,
+    position::Position,
 };
 use crate::compressed_move::CompressedMove;
 
@@ -153,8 +155,21 @@ impl Move {
         }
     }
 
+    /// Castling moves are written as the king moving to its destination
+    /// like in the C++ implementation, e.g. `e1g1` instead of `e1h1`
     pub fn as_uci(&self) -> String {
-        let mut uci = format!("{}{}", self.from, self.to);
+        let to = if self.move_type == MoveType::Castle {
+            let king_file = match self.castle_type() {
+                CastleType::Short => 6,
+                CastleType::Long => 2,
+            };
+
+            Square::new((self.from.index() & !7) + king_file)
+        } else {
+            self.to
+        };
+
+        let mut uci = format!("{}{}", self.from, to);
 
         if self.move_type == MoveType::Promotion {
             uci.push(match self.promoted_piece.piece_type() {
@@ -170,8 +185,392 @@ impl Move {
     }
 }
 
+impl Move {
+    /// Parse a move in UCI notation, the position is needed to tell castling
+    /// and en passant apart from normal moves. Castling is accepted both as
+    /// the king moving two squares and as the king moving onto its rook.
+    pub fn from_uci(pos: &Position, uci: &str) -> Option<Self> {
+        let from = Square::parse(uci.get(0..2)?)?;
+        let to = Square::parse(uci.get(2..4)?)?;
+        let piece = pos.piece_at(from);
+
+        if piece == Piece::none() {
+            return None;
+        }
+
+        match uci.len() {
+            4 => (),
+            5 => {
+                let piece_type = match uci.as_bytes()[4] {
+                    b'q' => PieceType::Queen,
+                    b'r' => PieceType::Rook,
+                    b'b' => PieceType::Bishop,
+                    b'n' => PieceType::Knight,
+                    _ => return None,
+                };
+
+                return Some(Self::promotion(
+                    from,
+                    to,
+                    Piece::new(piece_type, piece.color()),
+                ));
+            }
+            _ => return None,
+        }
+
+        if piece.piece_type() == PieceType::King {
+            if pos.piece_at(to) == Piece::new(PieceType::Rook, piece.color()) {
+                return Some(Self::castle(from, to));
+            }
+
+            if from.file() == File::E && from.rank() == to.rank() {
+                match to.file() {
+                    File::G => return Some(Self::castle(from, Square::new(from.index() + 3))),
+                    File::C => return Some(Self::castle(from, Square::new(from.index() - 4))),
+                    _ => (),
+                }
+            }
+        }
+
+        if piece.piece_type() == PieceType::Pawn && to == pos.ep_square() {
+            return Some(Self::en_passant(from, to));
+        }
+
+        Some(Self::normal(from, to))
+    }
+
+    /// Standard algebraic notation of the move in `pos`, checks are marked
+    /// with `+` and checkmates with `#`
+    pub fn as_san(&self, pos: &Position) -> String {
+        let piece = pos.piece_at(self.from);
+        let mut san = String::new();
+
+        if self.move_type == MoveType::Castle {
+            san.push_str(match self.castle_type() {
+                CastleType::Short => "O-O",
+                CastleType::Long => "O-O-O",
+            });
+        } else {
+            let is_capture =
+                self.move_type == MoveType::EnPassant || pos.piece_at(self.to) != Piece::none();
+
+            if piece.piece_type() == PieceType::Pawn {
+                if is_capture {
+                    san.push_str(&self.from.file().to_string());
+                }
+            } else {
+                san.push(piece_letter(piece.piece_type()));
+                san.push_str(&self.disambiguation(pos, piece));
+            }
+
+            if is_capture {
+                san.push('x');
+            }
+
+            san.push_str(&self.to.to_string());
+
+            if self.move_type == MoveType::Promotion {
+                san.push('=');
+                san.push(piece_letter(self.promoted_piece.piece_type()));
+            }
+        }
+
+        let after = pos.after_move(*self);
+        if after.is_checked(after.side_to_move()) {
+            san.push(if after.legal_moves().is_empty() {
+                '#'
+            } else {
+                '+'
+            });
+        }
+
+        san
+    }
+
+    /// Parse a move in standard algebraic notation, annotations like `+`, `#`,
+    /// `!` and `?` are ignored. Returns `None` unless exactly one legal move matches.
+    pub fn from_san(pos: &Position, san: &str) -> Option<Self> {
+        let san = san.trim_end_matches(['+', '#', '!', '?']);
+
+        let castle_type = match san {
+            "O-O" | "0-0" => Some(CastleType::Short),
+            "O-O-O" | "0-0-0" => Some(CastleType::Long),
+            _ => None,
+        };
+
+        if let Some(castle_type) = castle_type {
+            return pos
+                .legal_moves()
+                .into_iter()
+                .find(|mv| mv.move_type == MoveType::Castle && mv.castle_type() == castle_type);
+        }
+
+        let (piece_type, rest) = match san.chars().next()? {
+            'N' => (PieceType::Knight, &san[1..]),
+            'B' => (PieceType::Bishop, &san[1..]),
+            'R' => (PieceType::Rook, &san[1..]),
+            'Q' => (PieceType::Queen, &san[1..]),
+            'K' => (PieceType::King, &san[1..]),
+            _ => (PieceType::Pawn, san),
+        };
+
+        let (rest, promotion) = match rest.split_once('=') {
+            Some((rest, promotion)) => (rest, Some(promotion)),
+            None => (rest, None),
+        };
+
+        let promotion = match promotion {
+            Some("N") => Some(PieceType::Knight),
+            Some("B") => Some(PieceType::Bishop),
+            Some("R") => Some(PieceType::Rook),
+            Some("Q") => Some(PieceType::Queen),
+            Some(_) => return None,
+            None => None,
+        };
+
+        let to = Square::parse(rest.get(rest.len().checked_sub(2)?..)?)?;
+        let hint = &rest[..rest.len() - 2];
+        let is_capture = hint.ends_with('x');
+        let hint = hint.trim_end_matches('x');
+
+        let (file, rank) = match hint.as_bytes() {
+            [] => (None, None),
+            [f @ b'a'..=b'h'] => (Some(File::new((f - b'a') as u32)), None),
+            [r @ b'1'..=b'8'] => (None, Some(Rank::new((r - b'1') as u32))),
+            [f @ b'a'..=b'h', r @ b'1'..=b'8'] => (
+                Some(File::new((f - b'a') as u32)),
+                Some(Rank::new((r - b'1') as u32)),
+            ),
+            _ => return None,
+        };
+
+        let mut candidates = pos.legal_moves().into_iter().filter(|mv| {
+            let promoted =
+                (mv.move_type == MoveType::Promotion).then(|| mv.promoted_piece.piece_type());
+
+            mv.move_type != MoveType::Castle
+                && mv.to == to
+                && pos.piece_at(mv.from).piece_type() == piece_type
+                && file.is_none_or(|f| mv.from.file() == f)
+                && rank.is_none_or(|r| mv.from.rank() == r)
+                && promoted == promotion
+                // pawns only capture diagonally, the capture mark is optional for pieces
+                && (piece_type != PieceType::Pawn || is_capture == (mv.from.file() != to.file()))
+        });
+
+        let mv = candidates.next()?;
+        candidates.next().is_none().then_some(mv)
+    }
+
+    /// File and/or rank of the origin if other pieces of the same kind can
+    /// legally move to the same square
+    fn disambiguation(&self, pos: &Position, piece: Piece) -> String {
+        let stm = piece.color();
+        let others = Attacks::piece_attacks(piece.piece_type(), self.to, pos.occupied())
+            & pos.pieces_bb_color(stm, piece.piece_type());
+
+        let others = others
+            .iter()
+            .filter(|&sq| sq != self.from)
+            .filter(|&sq| !pos.after_move(Self::normal(sq, self.to)).is_checked(stm))
+            .collect::<Vec<_>>();
+
+        if others.is_empty() {
+            String::new()
+        } else if others.iter().all(|sq| sq.file() != self.from.file()) {
+            self.from.file().to_string()
+        } else if others.iter().all(|sq| sq.rank() != self.from.rank()) {
+            self.from.rank().to_string()
+        } else {
+            self.from.to_string()
+        }
+    }
+}
+
+fn piece_letter(piece_type: PieceType) -> char {
+    match piece_type {
+        PieceType::Pawn => 'P',
+        PieceType::Knight => 'N',
+        PieceType::Bishop => 'B',
+        PieceType::Rook => 'R',
+        PieceType::Queen => 'Q',
+        PieceType::King => 'K',
+        PieceType::None => panic!("Invalid piece type"),
+    }
+}
+
 impl Default for Move {
     fn default() -> Self {
         Self::null()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uci_roundtrip() {
+        let pos = Position::from_fen(
+            "r3k2r/pPppqpb1/bn2pnp1/3PN3/Pp2P3/2N2Q1p/1PPBBPP1/R3K2R b KQkq a3 0 1",
+        )
+        .unwrap();
+
+        let moves = [
+            ("e8g8", Move::castle(Square::E8, Square::H8)),
+            ("e8c8", Move::castle(Square::E8, Square::A8)),
+            ("b4a3", Move::en_passant(Square::new(25), Square::new(16))),
+            ("e7d6", Move::normal(Square::new(52), Square::new(43))),
+        ];
+
+        for (uci, mv) in moves {
+            assert_eq!(Move::from_uci(&pos, uci), Some(mv));
+            assert_eq!(mv.as_uci(), uci);
+        }
+
+        assert_eq!(
+            Move::from_uci(&pos, "e8h8"),
+            Some(Move::castle(Square::E8, Square::H8))
+        );
+    }
+
+    #[test]
+    fn test_uci_promotion() {
+        let pos = Position::from_fen("r3k3/1P6/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        let mv = Move::from_uci(&pos, "b7a8n").unwrap();
+
+        assert_eq!(
+            mv,
+            Move::promotion(Square::new(49), Square::A8, Piece::WHITE_KNIGHT)
+        );
+        assert_eq!(mv.as_uci(), "b7a8n");
+    }
+
+    #[test]
+    fn test_uci_malformed() {
+        let pos = Position::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+
+        for uci in ["", "e1", "e1e", "e2e4", "e1e2x", "e1e2qq", "i1e2"] {
+            assert!(Move::from_uci(&pos, uci).is_none(), "{uci}");
+        }
+    }
+
+    #[test]
+    fn test_san() {
+        let pos = Position::from_fen(
+            "r3k2r/pPppqpb1/bn2pnp1/3PN3/Pp2P3/2N2Q1p/1PPBBPP1/R3K2R b KQkq a3 0 1",
+        )
+        .unwrap();
+
+        let moves = [
+            ("e8g8", "O-O"),
+            ("b4a3", "bxa3"),
+            ("b4c3", "bxc3"),
+            ("h3g2", "hxg2"),
+            ("e7d6", "Qd6"),
+            ("a6e2", "Bxe2"),
+            ("f6e4", "Nxe4"),
+            ("b6d5", "Nbxd5"),
+            ("f6d5", "Nfxd5"),
+            ("f6h5", "Nh5"),
+        ];
+
+        for (uci, san) in moves {
+            let mv = Move::from_uci(&pos, uci).unwrap();
+            assert_eq!(mv.as_san(&pos), san, "{uci}");
+            assert_eq!(Move::from_san(&pos, san), Some(mv), "{san}");
+        }
+
+        let pos = Position::from_fen("r3k3/1P6/8/8/8/8/8/R3K2R w KQ - 0 1").unwrap();
+        let moves = [
+            ("b7a8q", "bxa8=Q+"),
+            ("b7b8n", "b8=N"),
+            ("a1a8", "Rxa8+"),
+            ("h1h8", "Rh8+"),
+            ("a1d1", "Rd1"),
+            ("e1g1", "O-O"),
+            ("e1c1", "O-O-O"),
+        ];
+
+        for (uci, san) in moves {
+            let mv = Move::from_uci(&pos, uci).unwrap();
+            assert_eq!(mv.as_san(&pos), san, "{uci}");
+            assert_eq!(Move::from_san(&pos, san), Some(mv), "{san}");
+        }
+
+        let pos = Position::from_fen("r3k3/8/8/8/8/8/8/4K3 b q - 0 1").unwrap();
+        let mv = Move::from_uci(&pos, "e8c8").unwrap();
+        assert_eq!(mv.as_san(&pos), "O-O-O");
+        assert_eq!(Move::from_san(&pos, "O-O-O"), Some(mv));
+
+        let pos = Position::from_fen("6k1/5ppp/8/8/8/8/8/R3K3 w Q - 0 1").unwrap();
+        let mate = Move::from_uci(&pos, "a1a8").unwrap();
+        assert_eq!(mate.as_san(&pos), "Ra8#");
+        assert_eq!(Move::from_san(&pos, "Ra8#"), Some(mate));
+
+        // the rook on e2 is pinned, so there is no need to disambiguate
+        let pos = Position::from_fen("4r2k/8/8/8/8/8/4R3/R3K3 w - - 0 1").unwrap();
+        let mv = Move::from_uci(&pos, "a1a2").unwrap();
+        assert_eq!(mv.as_san(&pos), "Ra2");
+        assert_eq!(Move::from_san(&pos, "Ra2"), Some(mv));
+
+        let pos = Position::from_fen("7k/8/8/8/8/R7/8/R3K3 w - - 0 1").unwrap();
+        let mv = Move::from_uci(&pos, "a1a2").unwrap();
+        assert_eq!(mv.as_san(&pos), "R1a2");
+        assert_eq!(Move::from_san(&pos, "R1a2"), Some(mv));
+        assert_eq!(Move::from_san(&pos, "Ra2"), None);
+    }
+
+    #[test]
+    fn test_san_parsing() {
+        let pos = Position::from_fen(
+            "r3k2r/pPppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPP1/R3K2R w KQkq - 0 1",
+        )
+        .unwrap();
+
+        let moves = [
+            ("O-O", "e1h1"),
+            ("0-0-0", "e1a1"),
+            ("Nxf7!?", "e5f7"),
+            ("Qxf6+", "f3f6"),
+            ("bxa8=Q", "b7a8q"),
+            ("b8=N", "b7b8n"),
+            ("a4", "a2a4"),
+            ("g3", "g2g3"),
+            ("Ncd1", "c3d1"),
+            ("Nc3d1", "c3d1"),
+            ("Rb1", "a1b1"),
+        ];
+
+        for (san, uci) in moves {
+            let mv = Move::from_san(&pos, san).unwrap();
+            assert_eq!(
+                mv.as_uci(),
+                Move::from_uci(&pos, uci).unwrap().as_uci(),
+                "{san}"
+            );
+        }
+
+        // illegal or malformed
+        for san in [
+            "Kf1x", "a5", "b8", "b8=K", "Bxh8", "exd6", "O-O-O-O", "", "Z4", "e",
+        ] {
+            assert!(Move::from_san(&pos, san).is_none(), "{san}");
+        }
+
+        let pos = Position::from_fen("8/8/8/2k5/2pP4/8/B7/4K3 b - d3 0 1").unwrap();
+        assert_eq!(
+            Move::from_san(&pos, "cxd3"),
+            Some(Move::en_passant(Square::new(26), Square::new(19)))
+        );
+
+        // castling through pieces or an attacked square
+        let pos =
+            Position::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+        assert_eq!(Move::from_san(&pos, "O-O"), None);
+        assert_eq!(Move::from_san(&pos, "O-O-O"), None);
+
+        let pos = Position::from_fen("4k3/8/8/8/8/8/5r2/4K2R w K - 0 1").unwrap();
+        assert_eq!(Move::from_san(&pos, "O-O"), None);
+    }
+}
@@ -0,0 +1,382 @@
+//! Move generation for [`Position`]. Castling moves are generated as the king
+//! moving onto its own rook, like they are stored in binpacks.
+
+use crate::chess::{
+    attacks::Attacks,
+    bitboard::Bitboard,
+    castling_rights::{CastleType, CastlingTraits},
+    color::Color,
+    coords::{Rank, Square},
+    piece::Piece,
+    piecetype::PieceType,
+    position::Position,
+    r#move::Move,
+};
+
+const PROMOTIONS: [PieceType; 4] = [
+    PieceType::Queen,
+    PieceType::Rook,
+    PieceType::Bishop,
+    PieceType::Knight,
+];
+
+const SLIDERS: [PieceType; 3] = [PieceType::Bishop, PieceType::Rook, PieceType::Queen];
+
+impl Position {
+    /// Moves that follow the movement rules of the pieces but may leave the
+    /// own king in check. Castling out of or through check isn't generated.
+    pub fn pseudo_legal_moves(&self) -> Vec<Move> {
+        let stm = self.side_to_move();
+        let mut moves = Vec::new();
+
+        let king = self.king_sq(stm);
+        for to in (Attacks::king(king) & !self.pieces_bb(stm)).iter() {
+            moves.push(Move::normal(king, to));
+        }
+
+        self.castling_moves(&mut moves);
+        self.piece_moves(&mut moves, Bitboard::new(!0), |_| Bitboard::new(!0));
+        self.en_passant_moves(&mut moves, false);
+
+        moves
+    }
+
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let stm = self.side_to_move();
+        let mut moves = Vec::new();
+
+        let king = self.king_sq(stm);
+        let checkers = self.attackers(king, !stm, self.occupied());
+
+        // sliders must not see through the king when it steps away from them
+        let occupied = self.occupied() & !Bitboard::from_square(king);
+        for to in (Attacks::king(king) & !self.pieces_bb(stm)).iter() {
+            if self.attackers(to, !stm, occupied).bits() == 0 {
+                moves.push(Move::normal(king, to));
+            }
+        }
+
+        if checkers.count() > 1 {
+            return moves;
+        }
+
+        // in check the checker has to be captured or blocked
+        let targets = match checkers.iter().next() {
+            Some(checker) => between(king, checker) | Bitboard::from_square(checker),
+            None => {
+                self.castling_moves(&mut moves);
+                Bitboard::new(!0)
+            }
+        };
+
+        let pins = self.pins(king);
+        self.piece_moves(&mut moves, targets, |sq| {
+            pins.iter()
+                .find(|&&(pinned, _)| pinned == sq)
+                .map_or(Bitboard::new(!0), |&(_, ray)| ray)
+        });
+
+        // en passant can uncover a check along the rank of both pawns, so
+        // these few moves are checked by playing them
+        self.en_passant_moves(&mut moves, true);
+
+        moves
+    }
+
+    pub fn is_legal(&self, mv: Move) -> bool {
+        self.legal_moves().contains(&mv)
+    }
+
+    /// Number of leaf nodes of the legal move tree with the given depth
+    pub fn perft(&self, depth: u32) -> u64 {
+        let moves = self.legal_moves();
+
+        match depth {
+            0 => 1,
+            1 => moves.len() as u64,
+            _ => moves
+                .into_iter()
+                .map(|mv| self.after_move(mv).perft(depth - 1))
+                .sum(),
+        }
+    }
+
+    /// Pieces of `color` that attack `sq` with the given occupancy
+    fn attackers(&self, sq: Square, color: Color, occupied: Bitboard) -> Bitboard {
+        let pieces = |pt| self.pieces_bb_color(color, pt);
+        let queens = pieces(PieceType::Queen);
+
+        (Attacks::pawn(!color, sq) & pieces(PieceType::Pawn))
+            | (Attacks::knight(sq) & pieces(PieceType::Knight))
+            | (Attacks::king(sq) & pieces(PieceType::King))
+            | (Attacks::bishop(sq, occupied) & (pieces(PieceType::Bishop) | queens))
+            | (Attacks::rook(sq, occupied) & (pieces(PieceType::Rook) | queens))
+    }
+
+    /// The pinned pieces of the side to move with the squares they can move to
+    fn pins(&self, king: Square) -> Vec<(Square, Bitboard)> {
+        let stm = self.side_to_move();
+        let their = self.pieces_bb(!stm);
+        let queens = self.pieces_bb_color(!stm, PieceType::Queen);
+
+        let snipers = (Attacks::bishop(king, their)
+            & (self.pieces_bb_color(!stm, PieceType::Bishop) | queens))
+            | (Attacks::rook(king, their) & (self.pieces_bb_color(!stm, PieceType::Rook) | queens));
+
+        snipers
+            .iter()
+            .filter_map(|sniper| {
+                let ray = between(king, sniper);
+                let blockers = ray & self.occupied();
+
+                (blockers.count() == 1 && (blockers & self.pieces_bb(stm)).bits() != 0).then(|| {
+                    (
+                        blockers.iter().next().unwrap(),
+                        ray | Bitboard::from_square(sniper),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Moves of all pieces except the king and en passant captures, `pin_mask`
+    /// gives the squares a piece on a square may move to
+    fn piece_moves(
+        &self,
+        moves: &mut Vec<Move>,
+        targets: Bitboard,
+        pin_mask: impl Fn(Square) -> Bitboard,
+    ) {
+        let stm = self.side_to_move();
+        let occupied = self.occupied();
+        let targets = targets & !self.pieces_bb(stm);
+
+        for pt in [PieceType::Knight].iter().chain(&SLIDERS) {
+            for from in self.pieces_bb_color(stm, *pt).iter() {
+                for to in
+                    (Attacks::piece_attacks(*pt, from, occupied) & targets & pin_mask(from)).iter()
+                {
+                    moves.push(Move::normal(from, to));
+                }
+            }
+        }
+
+        let (forward, start_rank) = match stm {
+            Color::White => (1, Rank::SECOND),
+            Color::Black => (-1, Rank::SEVENTH),
+        };
+
+        for from in self.pieces_bb_color(stm, PieceType::Pawn).iter() {
+            let allowed = targets & pin_mask(from);
+            let captures = Attacks::pawn(stm, from) & self.pieces_bb(!stm) & allowed;
+
+            for to in captures.iter() {
+                push_pawn_move(moves, from, to, stm);
+            }
+
+            // pawns never stand on the last rank, so the push stays on the board
+            let single = from.offset(0, forward).unwrap();
+            if self.piece_at(single) != Piece::none() {
+                continue;
+            }
+
+            if allowed.sq_set(single) {
+                push_pawn_move(moves, from, single, stm);
+            }
+
+            if from.rank() == start_rank {
+                let double = from.offset(0, 2 * forward).unwrap();
+
+                if self.piece_at(double) == Piece::none() && allowed.sq_set(double) {
+                    moves.push(Move::normal(from, double));
+                }
+            }
+        }
+    }
+
+    fn en_passant_moves(&self, moves: &mut Vec<Move>, legal: bool) {
+        let stm = self.side_to_move();
+        let ep = self.ep_square();
+
+        if ep == Square::NONE {
+            return;
+        }
+
+        for from in (Attacks::pawn(!stm, ep) & self.pieces_bb_color(stm, PieceType::Pawn)).iter() {
+            let mv = Move::en_passant(from, ep);
+
+            if !legal || !self.after_move(mv).is_checked(stm) {
+                moves.push(mv);
+            }
+        }
+    }
+
+    /// Castling moves that don't move the king out of, through or into check
+    fn castling_moves(&self, moves: &mut Vec<Move>) {
+        let stm = self.side_to_move();
+
+        for castle_type in [CastleType::Short, CastleType::Long] {
+            let mv = Move::from_castle(castle_type, stm);
+            let (king, rook) = (mv.from(), mv.to());
+
+            let is_possible = self
+                .castling_rights()
+                .contains(CastlingTraits::castling_rights(stm, castle_type))
+                && self.piece_at(king) == Piece::new(PieceType::King, stm)
+                && self.piece_at(rook) == Piece::new(PieceType::Rook, stm)
+                && (between(king, rook) & self.occupied()).bits() == 0;
+
+            if !is_possible {
+                continue;
+            }
+
+            let king_to = match castle_type {
+                CastleType::Short => king.offset(2, 0),
+                CastleType::Long => king.offset(-2, 0),
+            }
+            .unwrap();
+
+            let path = between(king, king_to) | Bitboard::from_square(king_to);
+            let is_safe = !self.is_checked(stm)
+                && path
+                    .iter()
+                    .all(|sq| self.attackers(sq, !stm, self.occupied()).bits() == 0);
+
+            if is_safe {
+                moves.push(mv);
+            }
+        }
+    }
+}
+
+fn push_pawn_move(moves: &mut Vec<Move>, from: Square, to: Square, stm: Color) {
+    let last_rank = match stm {
+        Color::White => Rank::EIGHTH,
+        Color::Black => Rank::FIRST,
+    };
+
+    if to.rank() == last_rank {
+        for pt in PROMOTIONS {
+            moves.push(Move::promotion(from, to, Piece::new(pt, stm)));
+        }
+    } else {
+        moves.push(Move::normal(from, to));
+    }
+}
+
+/// Squares strictly between two squares on the same line, empty if the
+/// squares aren't on a common rank, file or diagonal
+fn between(a: Square, b: Square) -> Bitboard {
+    let files = (b.index() & 7) as i32 - (a.index() & 7) as i32;
+    let ranks = (b.index() >> 3) as i32 - (a.index() >> 3) as i32;
+    let mut bb = Bitboard::new(0);
+
+    if a == b || (files != 0 && ranks != 0 && files.abs() != ranks.abs()) {
+        return bb;
+    }
+
+    let mut sq = a;
+    loop {
+        sq = sq.offset(files.signum(), ranks.signum()).unwrap();
+        if sq == b {
+            return bb;
+        }
+
+        bb |= Bitboard::from_square(sq);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn perft(fen: &str, counts: &[u64]) {
+        let pos = Position::from_fen(fen).unwrap();
+
+        for (depth, &count) in (1..).zip(counts) {
+            assert_eq!(pos.perft(depth), count, "{fen} depth {depth}");
+        }
+    }
+
+    #[test]
+    fn test_perft_startpos() {
+        perft(
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            &[20, 400, 8902, 197281],
+        );
+    }
+
+    #[test]
+    fn test_perft_kiwipete() {
+        perft(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            &[48, 2039, 97862],
+        );
+    }
+
+    #[test]
+    fn test_perft_endgame() {
+        perft(
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            &[14, 191, 2812, 43238],
+        );
+    }
+
+    #[test]
+    fn test_perft_promotions() {
+        perft(
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            &[6, 264, 9467],
+        );
+        perft(
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            &[44, 1486, 62379],
+        );
+    }
+
+    #[test]
+    fn test_perft_middlegame() {
+        perft(
+            "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+            &[46, 2079, 89890],
+        );
+    }
+
+    #[test]
+    fn test_legal_moves_match_pseudo_legal_moves() {
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            // horizontal pin of an en passant capture
+            "8/8/8/K2pP2r/8/8/8/4k3 w - d6 0 1",
+            // double check
+            "4k3/8/8/8/8/5n2/8/r3K3 w - - 0 1",
+            // pinned pawn that can capture its pinner
+            "4k3/8/8/8/8/2b5/3P4/4K3 w - - 0 1",
+        ];
+
+        for fen in fens {
+            let pos = Position::from_fen(fen).unwrap();
+            let stm = pos.side_to_move();
+
+            let mut expected = pos
+                .pseudo_legal_moves()
+                .into_iter()
+                .filter(|&mv| !pos.after_move(mv).is_checked(stm))
+                .map(|mv| mv.as_uci())
+                .collect::<Vec<_>>();
+            let mut legal = pos
+                .legal_moves()
+                .iter()
+                .map(|mv| mv.as_uci())
+                .collect::<Vec<_>>();
+
+            expected.sort();
+            legal.sort();
+            assert_eq!(legal, expected, "{fen}");
+        }
+
+        let pos = Position::from_fen("8/8/8/K2pP2r/8/8/8/4k3 w - d6 0 1").unwrap();
+        assert!(!pos.is_legal(Move::from_uci(&pos, "e5d6").unwrap()));
+        assert!(pos.is_legal(Move::from_uci(&pos, "e5e6").unwrap()));
+    }
+}
@@ -1,12 +1,18 @@
-use crate::chess::{
-    attacks::Attacks,
-    bitboard::Bitboard,
-    castling_rights::{CastleType, CastlingRights},
-    color::Color,
-    coords::Square,
-    piece::Piece,
-    piecetype::PieceType,
-    r#move::{Move, MoveType},
+use thiserror::Error;
+
+use crate::{
+    chess::{
+        attacks::Attacks,
+        bitboard::Bitboard,
+        castling_rights::{CastleType, CastlingRights, CastlingTraits},
+        color::Color,
+        coords::{Rank, Square},
+        piece::Piece,
+        piecetype::PieceType,
+        r#move::{Move, MoveType},
+    },
+    compressed_position::CompressedPosition,
+    training_data_entry::MAX_PLY,
 };
 
 #[derive(Debug, Clone, Copy)]
@@ -21,6 +27,17 @@ pub struct Position {
     enpassant: Square, // En passant target square
 }
 
+// Positions are compared the same way as in the C++ implementation,
+// which ignores the halfmove clock and the fullmove number.
+impl PartialEq for Position {
+    fn eq(&self, other: &Self) -> bool {
+        self.pieces == other.pieces
+            && self.stm == other.stm
+            && self.enpassant == other.enpassant
+            && self.castling_rights == other.castling_rights
+    }
+}
+
 impl Default for Position {
     fn default() -> Self {
         Self::new()
@@ -153,7 +170,7 @@ impl Position {
         if genuine_capture || piece.piece_type() == PieceType::Pawn {
             self.halfm = 0;
         } else {
-            self.halfm += 1;
+            self.halfm = self.halfm.saturating_add(1);
         }
 
         // Update fullmove number
@@ -223,6 +240,16 @@ impl Position {
         debug_assert!(self.bb[PieceType::King.ordinal() as usize].count_ones() == 2);
     }
 
+    pub fn compress(&self) -> CompressedPosition {
+        CompressedPosition::compress(self)
+    }
+
+    pub fn after_move(&self, mv: Move) -> Position {
+        let mut pos = *self;
+        pos.do_move(mv);
+        pos
+    }
+
     fn update_castling_rights(&mut self, from: Square, to: Square) {
         // Remove castling rights if king or rook moves
         if from == Square::E1 || to == Square::E1 {
@@ -273,6 +300,10 @@ impl Position {
         self.halfm = counter as u8;
     }
 
+    pub fn rule50_counter(&self) -> u16 {
+        self.halfm as u16
+    }
+
     #[inline(always)]
     pub fn place(&mut self, pc: Piece, sq: Square) {
         debug_assert!(pc != Piece::none());
@@ -303,6 +334,168 @@ impl Position {
         self.pieces[sq.index() as usize] = Piece::none();
     }
 
+    /// Parse a FEN, the move counters are optional. Positions that can't
+    /// occur in a game, like a side without a king, are rejected.
+    pub fn from_fen(fen: &str) -> Result<Self, FenError> {
+        let mut pos = Self::new();
+        let mut parts = fen.split_whitespace();
+
+        let board = parts.next().ok_or(FenError::MissingField("board"))?;
+        let ranks = board.split('/').collect::<Vec<_>>();
+        if ranks.len() != 8 {
+            return Err(FenError::InvalidBoard(board.to_string()));
+        }
+
+        for (rank, pieces) in (0..8).rev().zip(ranks) {
+            let mut file = 0;
+
+            for c in pieces.chars() {
+                if let Some(skip) = c.to_digit(10).filter(|d| (1..=8).contains(d)) {
+                    file += skip;
+                } else {
+                    if file >= 8 {
+                        return Err(FenError::InvalidBoard(board.to_string()));
+                    }
+
+                    let piece = piece_from_fen(c).ok_or(FenError::InvalidPiece(c))?;
+                    pos.place(piece, Square::new(rank * 8 + file));
+                    file += 1;
+                }
+            }
+
+            if file != 8 {
+                return Err(FenError::InvalidBoard(board.to_string()));
+            }
+        }
+
+        let stm = parts.next().ok_or(FenError::MissingField("side to move"))?;
+        pos.stm = match stm {
+            "w" => Color::White,
+            "b" => Color::Black,
+            _ => return Err(FenError::InvalidSideToMove(stm.to_string())),
+        };
+
+        let castling = parts
+            .next()
+            .ok_or(FenError::MissingField("castling rights"))?;
+        if castling != "-" {
+            for c in castling.chars() {
+                let rights = match c {
+                    'K' => CastlingRights::WHITE_KING_SIDE,
+                    'Q' => CastlingRights::WHITE_QUEEN_SIDE,
+                    'k' => CastlingRights::BLACK_KING_SIDE,
+                    'q' => CastlingRights::BLACK_QUEEN_SIDE,
+                    _ => return Err(FenError::InvalidCastlingRights(castling.to_string())),
+                };
+
+                if pos.castling_rights.contains(rights) {
+                    return Err(FenError::InvalidCastlingRights(castling.to_string()));
+                }
+
+                pos.castling_rights |= rights;
+            }
+        }
+
+        let ep = parts
+            .next()
+            .ok_or(FenError::MissingField("en passant square"))?;
+        pos.enpassant = match ep {
+            "-" => Square::NONE,
+            _ => Square::parse(ep).ok_or_else(|| FenError::InvalidEnPassant(ep.to_string()))?,
+        };
+
+        let counter = |value: &str| {
+            value
+                .parse::<u16>()
+                .map_err(|_| FenError::InvalidMoveCounter(value.to_string()))
+        };
+
+        if let Some(halfm) = parts.next() {
+            pos.halfm = u8::try_from(counter(halfm)?)
+                .map_err(|_| FenError::InvalidMoveCounter(halfm.to_string()))?;
+        }
+
+        if let Some(fullm) = parts.next() {
+            pos.fullm = counter(fullm)?.max(1);
+
+            if pos.fullm > MAX_FULLMOVE {
+                return Err(FenError::FullmoveTooLarge(pos.fullm));
+            }
+        }
+
+        if let Some(extra) = parts.next() {
+            return Err(FenError::TrailingField(extra.to_string()));
+        }
+
+        pos.validate(castling, ep)?;
+        Ok(pos)
+    }
+
+    fn validate(&self, castling: &str, ep: &str) -> Result<(), FenError> {
+        for color in [Color::White, Color::Black] {
+            let kings = self.pieces_bb_color(color, PieceType::King).count();
+            if kings != 1 {
+                return Err(FenError::KingCount(color, kings));
+            }
+
+            let count = |pt| self.pieces_bb_color(color, pt).count();
+            let pawns = count(PieceType::Pawn);
+            // pieces that must have been promoted pawns
+            let promoted = count(PieceType::Knight).saturating_sub(2)
+                + count(PieceType::Bishop).saturating_sub(2)
+                + count(PieceType::Rook).saturating_sub(2)
+                + count(PieceType::Queen).saturating_sub(1);
+
+            if self.pieces_bb(color).count() > 16 || pawns + promoted > 8 {
+                return Err(FenError::TooManyPieces(color));
+            }
+
+            for castle_type in [CastleType::Short, CastleType::Long] {
+                let rights = CastlingTraits::castling_rights(color, castle_type);
+                let mv = Move::from_castle(castle_type, color);
+
+                if self.castling_rights.contains(rights)
+                    && (self.piece_at(mv.from()) != Piece::new(PieceType::King, color)
+                        || self.piece_at(mv.to()) != Piece::new(PieceType::Rook, color))
+                {
+                    return Err(FenError::InvalidCastlingRights(castling.to_string()));
+                }
+            }
+        }
+
+        let pawns = self.bb[PieceType::Pawn.ordinal() as usize];
+        if let Some(sq) = Bitboard::new(pawns & 0xFF00_0000_0000_00FF).iter().next() {
+            return Err(FenError::PawnOnBackRank(sq));
+        }
+
+        if self.enpassant != Square::NONE {
+            // the pawn that was pushed two squares is in front of the ep
+            // square and the squares it passed are empty
+            let (ep_rank, forward) = match self.stm {
+                Color::White => (Rank::SIXTH, -8),
+                Color::Black => (Rank::THIRD, 8),
+            };
+
+            let ep_sq = self.enpassant.index() as i32;
+            let pawn = Square::new((ep_sq + forward) as u32);
+            let origin = Square::new((ep_sq - forward) as u32);
+
+            if self.enpassant.rank() != ep_rank
+                || self.piece_at(pawn) != Piece::new(PieceType::Pawn, !self.stm)
+                || self.piece_at(self.enpassant) != Piece::none()
+                || self.piece_at(origin) != Piece::none()
+            {
+                return Err(FenError::InvalidEnPassant(ep.to_string()));
+            }
+        }
+
+        if self.is_checked(!self.stm) {
+            return Err(FenError::OpponentInCheck(!self.stm));
+        }
+
+        Ok(())
+    }
+
     pub fn fen(&self) -> String {
         let mut fen = String::new();
 
@@ -439,3 +632,184 @@ impl Position {
         self.is_attacked(self.king_sq(c), !c)
     }
 }
+
+/// Largest fullmove number accepted in a FEN, the ply of later moves
+/// doesn't fit into a binpack stem
+pub const MAX_FULLMOVE: u16 = MAX_PLY / 2 + 1;
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum FenError {
+    #[error("Missing {0}")]
+    MissingField(&'static str),
+    #[error("Unexpected field {0}")]
+    TrailingField(String),
+    #[error("Invalid board {0}")]
+    InvalidBoard(String),
+    #[error("Invalid piece {0}")]
+    InvalidPiece(char),
+    #[error("Invalid side to move {0}")]
+    InvalidSideToMove(String),
+    #[error("Invalid castling rights {0}")]
+    InvalidCastlingRights(String),
+    #[error("Invalid en passant square {0}")]
+    InvalidEnPassant(String),
+    #[error("Invalid move counter {0}")]
+    InvalidMoveCounter(String),
+    #[error("Fullmove number {0} is larger than {MAX_FULLMOVE}")]
+    FullmoveTooLarge(u16),
+    #[error("{0:?} has {1} kings")]
+    KingCount(Color, u32),
+    #[error("{0:?} has too many pieces")]
+    TooManyPieces(Color),
+    #[error("Pawn on {0}")]
+    PawnOnBackRank(Square),
+    #[error("{0:?} is in check without being to move")]
+    OpponentInCheck(Color),
+}
+
+fn piece_from_fen(c: char) -> Option<Piece> {
+    let piece_type = match c.to_ascii_lowercase() {
+        'p' => PieceType::Pawn,
+        'n' => PieceType::Knight,
+        'b' => PieceType::Bishop,
+        'r' => PieceType::Rook,
+        'q' => PieceType::Queen,
+        'k' => PieceType::King,
+        _ => return None,
+    };
+
+    let color = if c.is_ascii_uppercase() {
+        Color::White
+    } else {
+        Color::Black
+    };
+
+    Some(Piece::new(piece_type, color))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fen_roundtrip() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w Kq f6 0 3",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 12 40",
+        ];
+
+        for fen in fens {
+            assert_eq!(Position::from_fen(fen).unwrap().fen(), fen);
+        }
+    }
+
+    #[test]
+    fn test_from_fen_without_move_counters() {
+        let pos = Position::from_fen("4k3/8/8/8/8/8/8/4K3 b - -").unwrap();
+
+        assert_eq!(pos.side_to_move(), Color::Black);
+        assert_eq!(pos.rule50_counter(), 0);
+        assert_eq!(pos.fen(), "4k3/8/8/8/8/8/8/4K3 b - - 0 1");
+    }
+
+    #[test]
+    fn test_from_fen_malformed() {
+        let fens = [
+            "",
+            "4k3/8/8/8/8/8/4K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/4K4 w - - 0 1",
+            "4k3/8/8/8/8/8/8/4X3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/4K3 x - - 0 1",
+            "4k3/8/8/8/8/8/8/4K3 w X - 0 1",
+            "4k3/8/8/8/8/8/8/4K3 w - e9 0 1",
+            "4k3/8/8/8/8/8/8/4K3 w - - a 1",
+            "4k3/8/8/8/8/8/8/4K3 w - - 0 1 extra",
+        ];
+
+        for fen in fens {
+            assert!(Position::from_fen(fen).is_err(), "{fen}");
+        }
+    }
+
+    #[test]
+    fn test_from_fen_validation() {
+        let cases = [
+            (
+                "4k3/8/8/8/8/8/8/8 w - - 0 1",
+                FenError::KingCount(Color::White, 0),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/3KK3 w - - 0 1",
+                FenError::KingCount(Color::White, 2),
+            ),
+            (
+                "4k3/8/8/8/8/8/PPPPPPPP/QQQQKQQQ w - - 0 1",
+                FenError::TooManyPieces(Color::White),
+            ),
+            (
+                "4k3/8/8/8/8/8/PPPPPPP1/QQBBKB2 w - - 0 1",
+                FenError::TooManyPieces(Color::White),
+            ),
+            (
+                "4k2P/8/8/8/8/8/8/4K3 w - - 0 1",
+                FenError::PawnOnBackRank(Square::H8),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w K - 0 1",
+                FenError::InvalidCastlingRights("K".to_string()),
+            ),
+            (
+                "r3k2r/8/8/8/8/8/8/R4K1R w Qq - 0 1",
+                FenError::InvalidCastlingRights("Qq".to_string()),
+            ),
+            (
+                "r3k2r/8/8/8/8/8/8/R3K2R w KKq - 0 1",
+                FenError::InvalidCastlingRights("KKq".to_string()),
+            ),
+            (
+                "4k3/8/8/3pP3/8/8/8/4K3 w - d3 0 1",
+                FenError::InvalidEnPassant("d3".to_string()),
+            ),
+            (
+                "4k3/8/8/4P3/8/8/8/4K3 w - d6 0 1",
+                FenError::InvalidEnPassant("d6".to_string()),
+            ),
+            (
+                "4k3/3p4/8/3pP3/8/8/8/4K3 w - d6 0 1",
+                FenError::InvalidEnPassant("d6".to_string()),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K2r b - - 0 1",
+                FenError::OpponentInCheck(Color::White),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w - - -1 1",
+                FenError::InvalidMoveCounter("-1".to_string()),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w - - 256 1",
+                FenError::InvalidMoveCounter("256".to_string()),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K3 b - - 0 65535",
+                FenError::FullmoveTooLarge(65535),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w - - 0 8193",
+                FenError::FullmoveTooLarge(8193),
+            ),
+        ];
+
+        for (fen, error) in cases {
+            assert_eq!(Position::from_fen(fen).unwrap_err(), error, "{fen}");
+        }
+
+        assert!(Position::from_fen("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1").is_ok());
+        assert!(Position::from_fen("4k3/8/8/8/8/8/8/4K2r w - - 0 1").is_ok());
+
+        let pos = Position::from_fen("4k3/8/8/8/8/8/8/4K3 b - - 255 8192").unwrap();
+        assert_eq!(pos.ply(), MAX_PLY);
+    }
+}
@@ -4,6 +4,7 @@ use crate::{
     chess::color::Color,
     chess::coords::{FlatSquareOffset, Rank, Square},
     chess::piece::Piece,
+    chess::piecetype::PieceType,
     chess::position::Position,
 };
 
@@ -35,6 +36,80 @@ impl CompressedPosition {
         }
     }
 
+    pub fn write_to_big_endian(&self, data: &mut [u8]) {
+        data[0..8].copy_from_slice(&self.occupied.bits().to_be_bytes());
+        data[8..24].copy_from_slice(&self.packed_state);
+    }
+
+    pub fn compress(pos: &Position) -> Self {
+        let occupied = pos.occupied();
+
+        debug_assert!(occupied.count() <= 32);
+
+        let compress_piece = |sq: Square| -> u8 {
+            let piece = pos.piece_at(sq);
+
+            if piece.piece_type() == PieceType::Pawn {
+                // the pawn that can be captured en passant
+                if pos.ep_square() != Square::NONE {
+                    let offset = if pos.side_to_move() == Color::White {
+                        FlatSquareOffset::new(0, -1)
+                    } else {
+                        FlatSquareOffset::new(0, 1)
+                    };
+
+                    if sq == pos.ep_square() + offset {
+                        return 12;
+                    }
+                }
+            } else if piece == Piece::WHITE_ROOK {
+                let castling_rights = pos.castling_rights();
+
+                if (sq == Square::A1 && castling_rights.contains(CastlingRights::WHITE_QUEEN_SIDE))
+                    || (sq == Square::H1
+                        && castling_rights.contains(CastlingRights::WHITE_KING_SIDE))
+                {
+                    return 13;
+                }
+            } else if piece == Piece::BLACK_ROOK {
+                let castling_rights = pos.castling_rights();
+
+                if (sq == Square::A8 && castling_rights.contains(CastlingRights::BLACK_QUEEN_SIDE))
+                    || (sq == Square::H8
+                        && castling_rights.contains(CastlingRights::BLACK_KING_SIDE))
+                {
+                    return 14;
+                }
+            } else if piece == Piece::BLACK_KING && pos.side_to_move() == Color::Black {
+                return 15;
+            }
+
+            piece.as_int() as u8
+        };
+
+        let mut packed_state = [0u8; 16];
+
+        let mut squares_iter = occupied.iter();
+        for chunk in packed_state.iter_mut() {
+            if let Some(sq) = squares_iter.next() {
+                *chunk = compress_piece(sq);
+            } else {
+                break;
+            }
+
+            if let Some(sq) = squares_iter.next() {
+                *chunk |= compress_piece(sq) << 4;
+            } else {
+                break;
+            }
+        }
+
+        Self {
+            occupied,
+            packed_state,
+        }
+    }
+
     pub fn decompress(&self) -> Position {
         let mut pos = Position::new();
         pos.set_castling_rights(CastlingRights::NONE);
@@ -99,3 +174,92 @@ impl CompressedPosition {
         pos
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::r#move::Move;
+
+    fn start_position() -> Position {
+        let mut pos = Position::new();
+
+        let back_rank = [
+            PieceType::Rook,
+            PieceType::Knight,
+            PieceType::Bishop,
+            PieceType::Queen,
+            PieceType::King,
+            PieceType::Bishop,
+            PieceType::Knight,
+            PieceType::Rook,
+        ];
+
+        for (file, pt) in back_rank.iter().enumerate() {
+            let file = file as u32;
+            pos.place(Piece::new(*pt, Color::White), Square::new(file));
+            pos.place(Piece::WHITE_PAWN, Square::new(8 + file));
+            pos.place(Piece::BLACK_PAWN, Square::new(48 + file));
+            pos.place(Piece::new(*pt, Color::Black), Square::new(56 + file));
+        }
+
+        pos.set_castling_rights(CastlingRights::ALL);
+        pos
+    }
+
+    fn roundtrip(pos: &Position) -> Position {
+        let mut buf = [0u8; 24];
+        pos.compress().write_to_big_endian(&mut buf);
+        CompressedPosition::read_from_big_endian(&buf).decompress()
+    }
+
+    #[test]
+    fn test_compress_start_position() {
+        let pos = start_position();
+        let decompressed = roundtrip(&pos);
+
+        assert_eq!(decompressed, pos);
+        assert_eq!(
+            decompressed.fen(),
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+        );
+    }
+
+    #[test]
+    fn test_compress_black_to_move_with_ep() {
+        let mut pos = start_position();
+        pos.do_move(Move::normal(Square::new(12), Square::new(28))); // e2e4
+        pos.do_move(Move::normal(Square::new(62), Square::new(45))); // g8f6
+        pos.do_move(Move::normal(Square::new(28), Square::new(36))); // e4e5
+        pos.do_move(Move::normal(Square::new(51), Square::new(35))); // d7d5
+
+        assert_eq!(pos.ep_square(), Square::new(43));
+        assert_eq!(roundtrip(&pos), pos);
+
+        pos.do_move(Move::normal(Square::new(6), Square::new(21))); // g1f3
+
+        assert_eq!(pos.side_to_move(), Color::Black);
+        assert_eq!(roundtrip(&pos), pos);
+    }
+
+    #[test]
+    fn test_compress_partial_castling_rights() {
+        let mut pos = start_position();
+        pos.set_castling_rights(CastlingRights::WHITE_QUEEN_SIDE | CastlingRights::BLACK_KING_SIDE);
+
+        let decompressed = roundtrip(&pos);
+        assert_eq!(decompressed, pos);
+        assert_eq!(decompressed.castling_rights(), pos.castling_rights());
+    }
+
+    #[test]
+    fn test_write_to_big_endian() {
+        let pos = start_position();
+
+        let mut buf = [0u8; 24];
+        pos.compress().write_to_big_endian(&mut buf);
+
+        assert_eq!(buf[0..8], 0xFFFF_0000_0000_FFFFu64.to_be_bytes());
+        // a1 and b1 hold a castling rook and a knight
+        assert_eq!(buf[8], 13 | (Piece::WHITE_KNIGHT.as_int() as u8) << 4);
+    }
+}
@@ -0,0 +1,533 @@
+//! Conversion between binpacks and the Stockfish `.bin` format, a flat list
+//! of 40 byte `PackedSfenValue` records. Every record holds a Huffman coded
+//! position (sfen), the score, the move, the ply and the game result.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::iter::FusedIterator;
+
+use crate::{
+    chess::{
+        castling_rights::CastlingRights,
+        color::Color,
+        coords::Square,
+        piece::Piece,
+        piecetype::PieceType,
+        position::Position,
+        r#move::{Move, MoveType},
+    },
+    training_data_entry::{TrainingDataEntry, MAX_PLY},
+};
+
+use super::{export::export_binpack, import_binpack, read_record, ConvertError, Result};
+
+pub const PACKED_SFEN_VALUE_SIZE: usize = 40;
+
+const PACKED_SFEN_SIZE: usize = 32;
+
+// Castling rights in the order they are stored in the sfen
+const CASTLING_RIGHTS: [CastlingRights; 4] = [
+    CastlingRights::WHITE_KING_SIDE,
+    CastlingRights::WHITE_QUEEN_SIDE,
+    CastlingRights::BLACK_KING_SIDE,
+    CastlingRights::BLACK_QUEEN_SIDE,
+];
+
+/// Huffman code and its length of every piece type but the king,
+/// indexed by the piece type ordinal
+const HUFFMAN_CODES: [(u32, u32); 5] = [
+    (0b0001, 4),
+    (0b0011, 4),
+    (0b0101, 4),
+    (0b0111, 4),
+    (0b1001, 4),
+];
+
+#[derive(Debug, Clone)]
+pub struct PackedSfenValue {
+    pub data: [u8; PACKED_SFEN_VALUE_SIZE],
+}
+
+impl Default for PackedSfenValue {
+    fn default() -> Self {
+        Self {
+            data: [0; PACKED_SFEN_VALUE_SIZE],
+        }
+    }
+}
+
+impl PackedSfenValue {
+    pub fn pack_entry(entry: &TrainingDataEntry) -> Self {
+        let mut packed = Self::default();
+
+        pack_position(&entry.pos, &mut packed.data[..PACKED_SFEN_SIZE]);
+
+        packed.data[32..34].copy_from_slice(&entry.score.to_le_bytes());
+        packed.data[34..36].copy_from_slice(&encode_move(entry.mv).to_le_bytes());
+        packed.data[36..38].copy_from_slice(&entry.ply.to_le_bytes());
+        packed.data[38] = entry.result as i8 as u8;
+
+        packed
+    }
+
+    /// Returns `None` if the sfen can't be decoded, the move, ply and result
+    /// are not validated
+    pub fn unpack_entry(&self) -> Option<TrainingDataEntry> {
+        let pos = unpack_position(&self.data[..PACKED_SFEN_SIZE])?;
+        let mv = decode_move(&pos, u16::from_le_bytes([self.data[34], self.data[35]]));
+
+        Some(TrainingDataEntry {
+            pos,
+            mv,
+            score: i16::from_le_bytes([self.data[32], self.data[33]]),
+            ply: u16::from_le_bytes([self.data[36], self.data[37]]),
+            result: self.data[38] as i8 as i16,
+        })
+    }
+}
+
+/// Bits are written starting with the least significant bit of every byte
+struct BitWriter<'a> {
+    data: &'a mut [u8],
+    cursor: usize,
+}
+
+impl BitWriter<'_> {
+    fn write(&mut self, value: u32, bits: u32) {
+        for i in 0..bits {
+            if value & (1 << i) != 0 {
+                self.data[self.cursor / 8] |= 1 << (self.cursor % 8);
+            }
+
+            self.cursor += 1;
+        }
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    cursor: usize,
+}
+
+impl BitReader<'_> {
+    fn read(&mut self, bits: u32) -> Option<u32> {
+        let mut value = 0;
+
+        for i in 0..bits {
+            let byte = self.data.get(self.cursor / 8)?;
+            value |= ((byte >> (self.cursor % 8)) as u32 & 1) << i;
+            self.cursor += 1;
+        }
+
+        Some(value)
+    }
+}
+
+fn pack_position(pos: &Position, data: &mut [u8]) {
+    let mut bits = BitWriter { data, cursor: 0 };
+    let stm = pos.side_to_move();
+
+    bits.write(stm.ordinal() as u32, 1);
+    bits.write(pos.king_sq(Color::White).index() & 63, 6);
+    bits.write(pos.king_sq(Color::Black).index() & 63, 6);
+
+    for rank in (0..8).rev() {
+        for file in 0..8 {
+            let piece = pos.piece_at(Square::new(rank * 8 + file));
+
+            if piece == Piece::none() {
+                bits.write(0, 1);
+            } else if piece.piece_type() != PieceType::King {
+                let (code, len) = HUFFMAN_CODES[piece.piece_type().ordinal() as usize];
+                bits.write(code, len);
+                bits.write(piece.color().ordinal() as u32, 1);
+            }
+        }
+    }
+
+    for rights in CASTLING_RIGHTS {
+        bits.write(pos.castling_rights().contains(rights) as u32, 1);
+    }
+
+    if pos.ep_square() == Square::NONE {
+        bits.write(0, 1);
+    } else {
+        bits.write(1, 1);
+        bits.write(pos.ep_square().index(), 6);
+    }
+
+    let rule50 = pos.rule50_counter() as u32;
+    let fullmove = (pos.ply() / 2 + 1) as u32;
+
+    bits.write(rule50, 6);
+    bits.write(fullmove, 8);
+    bits.write(fullmove >> 8, 8);
+    bits.write(rule50 >> 6, 1);
+}
+
+fn unpack_position(data: &[u8]) -> Option<Position> {
+    let mut bits = BitReader { data, cursor: 0 };
+    let mut pos = Position::new();
+
+    pos.set_side_to_move(Color::from_ordinal(bits.read(1)? as u8));
+
+    let kings = [Square::new(bits.read(6)?), Square::new(bits.read(6)?)];
+    if kings[0] == kings[1] {
+        return None;
+    }
+
+    pos.place(Piece::WHITE_KING, kings[0]);
+    pos.place(Piece::BLACK_KING, kings[1]);
+
+    for rank in (0..8).rev() {
+        for file in 0..8 {
+            let sq = Square::new(rank * 8 + file);
+
+            if kings.contains(&sq) {
+                continue;
+            }
+
+            if let Some(piece) = read_piece(&mut bits)? {
+                pos.place(piece, sq);
+            }
+        }
+    }
+
+    if pos.occupied().count() > 32 || has_pawn_on_back_rank(&pos) {
+        return None;
+    }
+
+    for rights in CASTLING_RIGHTS {
+        if bits.read(1)? == 1 {
+            pos.add_castling_rights(rights);
+        }
+    }
+
+    if bits.read(1)? == 1 {
+        // the pawn that just moved two squares has to be in front of it
+        let ep = Square::new(bits.read(6)?);
+        let stm = pos.side_to_move();
+        let ep_rank = if stm == Color::White { 5 } else { 2 };
+
+        if ep.index() / 8 != ep_rank
+            || pos.piece_at(Square::new(ep.index() ^ 8)) != Piece::new(PieceType::Pawn, !stm)
+        {
+            return None;
+        }
+
+        pos.set_ep_square_unchecked(ep);
+    }
+
+    let mut rule50 = bits.read(6)?;
+    let fullmove = bits.read(8)? | bits.read(8)? << 8;
+    rule50 |= bits.read(1)? << 6;
+
+    pos.set_rule50_counter(rule50 as u16);
+    pos.set_ply((fullmove.max(1) - 1).saturating_mul(2).min(u16::MAX as u32) as u16);
+
+    Some(pos)
+}
+
+fn has_pawn_on_back_rank(pos: &Position) -> bool {
+    let pawns = pos.pieces_bb_color(Color::White, PieceType::Pawn)
+        | pos.pieces_bb_color(Color::Black, PieceType::Pawn);
+
+    pawns.bits() & 0xFF00_0000_0000_00FF != 0
+}
+
+/// Returns `Some(None)` for an empty square and `None` for an invalid code
+fn read_piece(bits: &mut BitReader) -> Option<Option<Piece>> {
+    if bits.read(1)? == 0 {
+        return Some(None);
+    }
+
+    let code = 1 | bits.read(3)? << 1;
+    let piece_type = HUFFMAN_CODES.iter().position(|&(c, _)| c == code)?;
+    let color = Color::from_ordinal(bits.read(1)? as u8);
+
+    Some(Some(Piece::new(
+        PieceType::from_ordinal(piece_type as u8),
+        color,
+    )))
+}
+
+/// Stockfish's 16 bit move encoding, castling is stored as the king
+/// capturing its own rook like in binpacks
+fn encode_move(mv: Move) -> u16 {
+    let mut data = ((mv.from().index() & 63) << 6 | (mv.to().index() & 63)) as u16;
+
+    match mv.mtype() {
+        MoveType::Normal => (),
+        MoveType::Promotion => {
+            data |= 1 << 14;
+            data |= (mv.promoted_piece().piece_type().ordinal() as u16 - 1) << 12;
+        }
+        MoveType::EnPassant => data |= 2 << 14,
+        MoveType::Castle => data |= 3 << 14,
+    }
+
+    data
+}
+
+fn decode_move(pos: &Position, data: u16) -> Move {
+    let from = Square::new(((data >> 6) & 63) as u32);
+    let to = Square::new((data & 63) as u32);
+
+    match data >> 14 {
+        1 => {
+            let piece_type = PieceType::from_ordinal(((data >> 12) & 3) as u8 + 1);
+            Move::promotion(from, to, Piece::new(piece_type, pos.side_to_move()))
+        }
+        2 => Move::en_passant(from, to),
+        3 => Move::castle(from, to),
+        _ => Move::normal(from, to),
+    }
+}
+
+pub fn write_bin_entry<W: Write>(out: &mut W, entry: &TrainingDataEntry) -> io::Result<()> {
+    out.write_all(&PackedSfenValue::pack_entry(entry).data)
+}
+
+/// Reads entries from `.bin` data
+#[derive(Debug)]
+pub struct BinReader<R> {
+    input: R,
+    num_read: u64,
+}
+
+impl<R: Read> BinReader<R> {
+    pub fn new(input: R) -> Self {
+        Self { input, num_read: 0 }
+    }
+
+    /// Read the next entry, returns `None` at the end of the input
+    pub fn next_entry(&mut self) -> Result<Option<TrainingDataEntry>> {
+        let mut packed = PackedSfenValue::default();
+        let len = read_record(&mut self.input, &mut packed.data)?;
+
+        if len == 0 {
+            return Ok(None);
+        }
+
+        let index = self.num_read;
+        self.num_read += 1;
+
+        let error = |message: String| ConvertError::InvalidEntry { index, message };
+
+        if len < PACKED_SFEN_VALUE_SIZE {
+            return Err(error(format!("Entry is truncated to {len} bytes")));
+        }
+
+        let entry = packed
+            .unpack_entry()
+            .ok_or_else(|| error("Invalid packed sfen".to_string()))?;
+
+        // also rejects flags that don't match the moved piece and squares
+        if !entry.pos.is_legal(entry.mv) {
+            return Err(error(format!("Illegal move {}", entry.mv.as_uci())));
+        }
+
+        if !(-1..=1).contains(&entry.result) {
+            return Err(error(format!("Invalid result {}", entry.result)));
+        }
+
+        if entry.ply > MAX_PLY {
+            return Err(error(format!("Ply {} is larger than {MAX_PLY}", entry.ply)));
+        }
+
+        Ok(Some(entry))
+    }
+}
+
+impl<R: Read> Iterator for BinReader<R> {
+    type Item = Result<TrainingDataEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().transpose()
+    }
+}
+
+impl<R: Read> FusedIterator for BinReader<R> {}
+
+/// Convert a binpack to a `.bin` file, returns the number of entries
+pub fn binpack_to_bin(input: &str, output: &str) -> Result<u64> {
+    export_binpack(input, output, |entry| {
+        PackedSfenValue::pack_entry(entry).data
+    })
+}
+
+/// Convert a `.bin` file to a binpack, returns the number of entries
+pub fn bin_to_binpack(input: &str, output: &str, append: bool) -> Result<u64> {
+    let reader = BinReader::new(BufReader::new(File::open(input)?));
+    import_binpack(
+        output,
+        append,
+        reader.map(|entry| entry.map(std::iter::once)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::test_util::read_ep1;
+    use tempfile::NamedTempFile;
+
+    const STARTPOS: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    #[test]
+    fn test_pack_startpos() {
+        let pos = Position::from_fen(STARTPOS).unwrap();
+        let entry = TrainingDataEntry {
+            pos,
+            mv: Move::from_uci(&pos, "e2e4").unwrap(),
+            score: -25,
+            ply: 0,
+            result: -1,
+        };
+
+        let packed = PackedSfenValue::pack_entry(&entry);
+
+        // side to move, e1 and e8 for the kings, then a black rook and knight
+        assert_eq!(packed.data[..3], [0x08, 0xFE, 0xCE]);
+        assert_eq!(packed.data[32..], [0xE7, 0xFF, 0x1C, 0x03, 0, 0, 0xFF, 0]);
+
+        let unpacked = packed.unpack_entry().unwrap();
+        assert_eq!(unpacked, entry);
+        assert_eq!(unpacked.pos.fen(), STARTPOS);
+    }
+
+    #[test]
+    fn test_bin_roundtrip() {
+        let fens = [
+            "r3k2r/8/8/8/8/8/8/R3K2R b Kq - 57 120",
+            "8/8/8/3pP3/8/8/8/4K2k w - d6 0 2",
+            "4k3/8/8/8/8/8/8/4K3 w - - 110 300",
+        ];
+
+        for fen in fens {
+            let pos = Position::from_fen(fen).unwrap();
+            let entry = TrainingDataEntry {
+                pos,
+                mv: Move::from_uci(&pos, "e1e2").unwrap(),
+                score: 100,
+                ply: 7,
+                result: 1,
+            };
+
+            let unpacked = PackedSfenValue::pack_entry(&entry).unpack_entry().unwrap();
+            assert_eq!(unpacked, entry);
+            assert_eq!(unpacked.pos.fen(), fen);
+        }
+
+        for entry in read_ep1() {
+            let unpacked = PackedSfenValue::pack_entry(&entry).unpack_entry().unwrap();
+            assert_eq!(unpacked, entry);
+            assert_eq!(unpacked.pos.fen(), entry.pos.fen());
+        }
+    }
+
+    #[test]
+    fn test_move_encoding() {
+        let pos = Position::from_fen("r3k2r/1P6/8/3pP3/8/8/8/R3K2R w KQkq d6 0 1").unwrap();
+
+        for uci in ["b7a8q", "b7b8n", "e5d6", "e1g1", "e1c1", "a1a8"] {
+            let mv = Move::from_uci(&pos, uci).unwrap();
+            assert_eq!(decode_move(&pos, encode_move(mv)), mv, "{uci}");
+        }
+
+        assert_eq!(encode_move(Move::from_uci(&pos, "e1g1").unwrap()), 0xC107);
+    }
+
+    #[test]
+    fn test_file_conversion_roundtrip() {
+        let bin = NamedTempFile::new().unwrap();
+        let binpack = NamedTempFile::new().unwrap();
+        let bin_path = bin.path().to_str().unwrap();
+        let binpack_path = binpack.path().to_str().unwrap();
+
+        assert_eq!(binpack_to_bin("./test/ep1.binpack", bin_path).unwrap(), 3);
+        assert_eq!(std::fs::metadata(bin_path).unwrap().len(), 3 * 40);
+        assert_eq!(bin_to_binpack(bin_path, binpack_path, false).unwrap(), 3);
+
+        let expected = std::fs::read("./test/ep1.binpack").unwrap();
+        assert_eq!(std::fs::read(binpack_path).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_invalid_entries() {
+        let entry = read_ep1()[0];
+        let packed = PackedSfenValue::pack_entry(&entry).data;
+
+        let truncated = [&packed[..], &packed[..20]].concat();
+        let mut reader = BinReader::new(truncated.as_slice());
+        assert!(reader.next_entry().unwrap().is_some());
+        assert!(matches!(
+            reader.next_entry(),
+            Err(ConvertError::InvalidEntry { index: 1, .. })
+        ));
+
+        // both kings on a1
+        let mut same_kings = packed;
+        same_kings[..2].fill(0);
+        assert!(BinReader::new(same_kings.as_slice()).next_entry().is_err());
+
+        // only invalid piece codes
+        let mut invalid_codes = packed;
+        invalid_codes[2..32].fill(0xFF);
+        assert!(BinReader::new(invalid_codes.as_slice())
+            .next_entry()
+            .is_err());
+
+        // a1a1
+        let mut illegal_move = packed;
+        illegal_move[34..36].fill(0);
+        assert!(BinReader::new(illegal_move.as_slice())
+            .next_entry()
+            .is_err());
+
+        let mut invalid_result = packed;
+        invalid_result[38] = 2;
+        assert!(BinReader::new(invalid_result.as_slice())
+            .next_entry()
+            .is_err());
+
+        let mut invalid_ply = packed;
+        invalid_ply[36..38].copy_from_slice(&(MAX_PLY + 1).to_le_bytes());
+        assert!(BinReader::new(invalid_ply.as_slice()).next_entry().is_err());
+    }
+
+    #[test]
+    fn test_unpack_invalid_position() {
+        let mut pos = Position::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        let mut data = [0; PACKED_SFEN_SIZE];
+        pack_position(&pos, &mut data);
+        assert!(unpack_position(&data).is_some());
+
+        pos.place(Piece::BLACK_PAWN, Square::A1);
+        pack_position(&pos, &mut data);
+        assert!(unpack_position(&data).is_none());
+
+        // 33 pieces
+        let mut pos = Position::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        for index in 8..39 {
+            pos.place(Piece::WHITE_KNIGHT, Square::new(index));
+        }
+        let mut data = [0; PACKED_SFEN_SIZE];
+        pack_position(&pos, &mut data);
+        assert!(unpack_position(&data).is_none());
+
+        // no pawn in front of the en passant square
+        for (fen, is_valid) in [
+            ("4k3/8/8/3pP3/8/8/8/4K3 w - - 0 2", true),
+            ("4k3/8/8/3nP3/8/8/8/4K3 w - - 0 2", false),
+            ("4k3/8/8/3pP3/8/8/8/4K3 b - - 0 2", false),
+        ] {
+            let mut pos = Position::from_fen(fen).unwrap();
+            pos.set_ep_square_unchecked(Square::new(43));
+
+            let mut data = [0; PACKED_SFEN_SIZE];
+            pack_position(&pos, &mut data);
+            assert_eq!(unpack_position(&data).is_some(), is_valid, "{fen}");
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Export to bulletformat, the 32 byte `ChessBoard` records read by the bullet
+//! trainer. Bullet expects boards from the perspective of the side to move,
+//! so boards with black to move are always flipped and
+//! [`ExportOptions::stm_perspective`] is ignored.
+
+use crate::{chess::color::Color, training_data_entry::TrainingDataEntry};
+
+use super::{
+    export::{export_binpack, ExportOptions},
+    Result,
+};
+
+pub const CHESS_BOARD_SIZE: usize = 32;
+
+/// Occupancy, the pieces as 4 bit values in square order, score, result,
+/// the king square of white and the mirrored king square of black
+#[derive(Debug, Clone)]
+pub struct ChessBoard {
+    pub data: [u8; CHESS_BOARD_SIZE],
+}
+
+impl ChessBoard {
+    pub fn pack_entry(entry: &TrainingDataEntry, options: &ExportOptions) -> Self {
+        let options = &ExportOptions {
+            stm_perspective: true,
+            ..options.clone()
+        };
+        let pos = options.orient(&entry.pos);
+        let mut data = [0; CHESS_BOARD_SIZE];
+
+        data[..8].copy_from_slice(&pos.occupied().bits().to_le_bytes());
+
+        for (i, sq) in pos.occupied().iter().enumerate() {
+            let piece = pos.piece_at(sq);
+            let value = piece.color().ordinal() << 3 | piece.piece_type().ordinal();
+
+            data[8 + i / 2] |= value << (4 * (i % 2));
+        }
+
+        data[24..26].copy_from_slice(&options.score(entry).to_le_bytes());
+        data[26] = options.wdl(entry);
+        data[27] = pos.king_sq(Color::White).index() as u8;
+        data[28] = (pos.king_sq(Color::Black).index() ^ 56) as u8;
+
+        Self { data }
+    }
+}
+
+/// Convert a binpack to a bulletformat file, returns the number of entries
+pub fn binpack_to_bullet(input: &str, output: &str, options: &ExportOptions) -> Result<u64> {
+    export_binpack(input, output, |entry| {
+        ChessBoard::pack_entry(entry, options).data
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::{position::Position, r#move::Move};
+    use tempfile::NamedTempFile;
+
+    fn startpos_entry() -> TrainingDataEntry {
+        let pos =
+            Position::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+
+        TrainingDataEntry {
+            pos,
+            mv: Move::from_uci(&pos, "e2e4").unwrap(),
+            score: 30,
+            ply: 0,
+            result: 1,
+        }
+    }
+
+    #[test]
+    fn test_pack_startpos() {
+        let board = ChessBoard::pack_entry(&startpos_entry(), &ExportOptions::default());
+
+        assert_eq!(board.data[..8], 0xFFFF_0000_0000_FFFFu64.to_le_bytes());
+        // rook, knight, bishop, queen, king, bishop, knight, rook
+        assert_eq!(board.data[8..12], [0x13, 0x42, 0x25, 0x31]);
+        // white pawns, then black pawns
+        assert_eq!(board.data[12..16], [0x00; 4]);
+        assert_eq!(board.data[16..20], [0x88; 4]);
+        assert_eq!(board.data[20..24], [0x9B, 0xCA, 0xAD, 0xB9]);
+        assert_eq!(board.data[24..], [30, 0, 2, 4, 4, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_stm_perspective() {
+        let white = startpos_entry();
+        let black = TrainingDataEntry {
+            pos: white.pos.after_move(white.mv),
+            mv: Move::null(),
+            score: -30,
+            ply: 1,
+            result: -1,
+        };
+
+        let board = ChessBoard::pack_entry(&black, &ExportOptions::default());
+        let occupied = black.pos.occupied().bits().swap_bytes();
+
+        assert_eq!(board.data[..8], occupied.to_le_bytes());
+        assert_eq!(board.data[8..12], [0x13, 0x42, 0x25, 0x31]);
+        assert_eq!(board.data[24..29], [0xE2, 0xFF, 0, 4, 4]);
+
+        // the option is ignored, bulletformat is always relative to the side to move
+        let options = ExportOptions {
+            stm_perspective: false,
+            ..Default::default()
+        };
+        assert_eq!(ChessBoard::pack_entry(&black, &options).data, board.data);
+    }
+
+    #[test]
+    fn test_export_file() {
+        let out = NamedTempFile::new().unwrap();
+        let path = out.path().to_str().unwrap();
+
+        let count = binpack_to_bullet("./test/ep1.binpack", path, &ExportOptions::default());
+        assert_eq!(count.unwrap(), 3);
+        assert_eq!(std::fs::metadata(path).unwrap().len(), 3 * 32);
+    }
+}
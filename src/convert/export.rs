@@ -0,0 +1,210 @@
+//! Options shared by the exporters to fixed size records of other trainers
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::{
+    chess::{
+        castling_rights::CastlingRights, color::Color, coords::Square, piece::Piece,
+        position::Position,
+    },
+    reader::training_data_reader::CompressedTrainingDataEntryReader,
+    training_data_entry::TrainingDataEntry,
+};
+
+use super::Result;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportOptions {
+    /// Flip the board vertically and swap the colors if black is to move, so
+    /// the side to move is always white. Scores and results are relative to
+    /// white of the exported board.
+    pub stm_perspective: bool,
+    /// Factor the scores are multiplied with
+    pub score_scale: f32,
+    /// Scores are clamped to this absolute value after scaling
+    pub max_abs_score: i16,
+    pub wdl: WdlMapping,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            stm_perspective: false,
+            score_scale: 1.0,
+            max_abs_score: i16::MAX,
+            wdl: WdlMapping::GameResult,
+        }
+    }
+}
+
+/// Where the win/draw/loss value of a record comes from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WdlMapping {
+    GameResult,
+    /// A win or a loss if the unscaled score is at least `threshold` in
+    /// absolute value, a draw otherwise
+    Score {
+        threshold: i16,
+    },
+}
+
+impl ExportOptions {
+    /// The position as it is exported
+    pub(crate) fn orient(&self, pos: &Position) -> Position {
+        if self.stm_perspective && pos.side_to_move() == Color::Black {
+            flipped(pos)
+        } else {
+            *pos
+        }
+    }
+
+    /// Scaled and clamped score relative to white of the exported board
+    pub(crate) fn score(&self, entry: &TrainingDataEntry) -> i16 {
+        let score = self.relative(entry, entry.score as i32) as f32 * self.score_scale;
+        let max = self.max_abs_score.unsigned_abs() as f32;
+
+        score
+            .round()
+            .clamp(-max, max)
+            .clamp(i16::MIN as f32, i16::MAX as f32) as i16
+    }
+
+    /// 0 for a loss, 1 for a draw and 2 for a win of white of the exported board
+    pub(crate) fn wdl(&self, entry: &TrainingDataEntry) -> u8 {
+        let result = match self.wdl {
+            WdlMapping::GameResult => entry.result as i32,
+            WdlMapping::Score { threshold } => {
+                let score = entry.score as i32;
+                let threshold = threshold as i32;
+
+                if score >= threshold {
+                    1
+                } else if score <= -threshold {
+                    -1
+                } else {
+                    0
+                }
+            }
+        };
+
+        (self.relative(entry, result.signum()) + 1) as u8
+    }
+
+    // binpack scores and results are relative to the side to move
+    fn relative(&self, entry: &TrainingDataEntry, value: i32) -> i32 {
+        if self.stm_perspective || entry.pos.side_to_move() == Color::White {
+            value
+        } else {
+            -value
+        }
+    }
+}
+
+/// The position seen from the other side, with mirrored ranks and swapped colors
+fn flipped(pos: &Position) -> Position {
+    let mut flipped = Position::new();
+
+    for sq in pos.occupied().iter() {
+        let piece = pos.piece_at(sq);
+        flipped.place(
+            Piece::new(piece.piece_type(), !piece.color()),
+            Square::new(sq.index() ^ 56),
+        );
+    }
+
+    let rights = pos.castling_rights();
+    for (from, to) in [
+        (
+            CastlingRights::WHITE_KING_SIDE,
+            CastlingRights::BLACK_KING_SIDE,
+        ),
+        (
+            CastlingRights::WHITE_QUEEN_SIDE,
+            CastlingRights::BLACK_QUEEN_SIDE,
+        ),
+        (
+            CastlingRights::BLACK_KING_SIDE,
+            CastlingRights::WHITE_KING_SIDE,
+        ),
+        (
+            CastlingRights::BLACK_QUEEN_SIDE,
+            CastlingRights::WHITE_QUEEN_SIDE,
+        ),
+    ] {
+        if rights.contains(from) {
+            flipped.add_castling_rights(to);
+        }
+    }
+
+    if pos.ep_square() != Square::NONE {
+        flipped.set_ep_square_unchecked(Square::new(pos.ep_square().index() ^ 56));
+    }
+
+    flipped.set_side_to_move(!pos.side_to_move());
+    flipped.set_rule50_counter(pos.rule50_counter());
+    flipped.set_ply(pos.ply());
+
+    flipped
+}
+
+/// Write a record for every entry of a binpack, returns the number of entries
+pub(crate) fn export_binpack<const N: usize>(
+    input: &str,
+    output: &str,
+    pack: impl Fn(&TrainingDataEntry) -> [u8; N],
+) -> Result<u64> {
+    let reader = CompressedTrainingDataEntryReader::new(input)?;
+    let mut out = BufWriter::new(File::create(output)?);
+    let mut count = 0;
+
+    for entry in reader {
+        out.write_all(&pack(&entry?))?;
+        count += 1;
+    }
+
+    out.flush()?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::test_util::entry;
+
+    #[test]
+    fn test_flipped() {
+        let pos = Position::from_fen("r3k2r/8/8/3pP3/8/8/8/4K2R w Kq d6 0 12").unwrap();
+        assert_eq!(
+            flipped(&pos).fen(),
+            "4k2r/8/8/8/3Pp3/8/8/R3K2R b Qk d3 0 12"
+        );
+        assert_eq!(flipped(&flipped(&pos)), pos);
+    }
+
+    #[test]
+    fn test_score_and_wdl() {
+        let black = entry("4k3/8/8/8/8/8/8/4K3 b - - 0 1", 300, 1);
+
+        let options = ExportOptions::default();
+        assert_eq!(options.score(&black), -300);
+        assert_eq!(options.wdl(&black), 0);
+        assert_eq!(options.orient(&black.pos), black.pos);
+
+        let options = ExportOptions {
+            stm_perspective: true,
+            score_scale: 2.0,
+            max_abs_score: 500,
+            wdl: WdlMapping::Score { threshold: 400 },
+        };
+        assert_eq!(options.score(&black), 500);
+        assert_eq!(options.wdl(&black), 1);
+        assert_eq!(options.orient(&black.pos).side_to_move(), Color::White);
+
+        let white = entry("4k3/8/8/8/8/8/8/4K3 w - - 0 1", -1000, 0);
+        assert_eq!(options.score(&white), -500);
+        assert_eq!(options.wdl(&white), 0);
+        assert_eq!(ExportOptions::default().score(&white), -1000);
+        assert_eq!(ExportOptions::default().wdl(&white), 1);
+    }
+}
@@ -0,0 +1,255 @@
+//! Export to marlinformat, the 32 byte `PackedBoard` records used by bullet
+//! and several other trainers. Scores and results are relative to white.
+
+use crate::{
+    chess::{
+        bitboard::Bitboard, castling_rights::CastlingRights, color::Color, coords::Square,
+        piece::Piece, piecetype::PieceType, position::Position,
+    },
+    training_data_entry::TrainingDataEntry,
+};
+
+use super::{
+    export::{export_binpack, ExportOptions},
+    Result,
+};
+
+pub const PACKED_BOARD_SIZE: usize = 32;
+
+/// Piece value of a rook that can still castle
+const UNMOVED_ROOK: u8 = 6;
+
+const CASTLING_ROOKS: [(CastlingRights, Piece, Square); 4] = [
+    (
+        CastlingRights::WHITE_KING_SIDE,
+        Piece::WHITE_ROOK,
+        Square::H1,
+    ),
+    (
+        CastlingRights::WHITE_QUEEN_SIDE,
+        Piece::WHITE_ROOK,
+        Square::A1,
+    ),
+    (
+        CastlingRights::BLACK_KING_SIDE,
+        Piece::BLACK_ROOK,
+        Square::H8,
+    ),
+    (
+        CastlingRights::BLACK_QUEEN_SIDE,
+        Piece::BLACK_ROOK,
+        Square::A8,
+    ),
+];
+
+/// Occupancy, the pieces as 4 bit values in square order, side to move and
+/// en passant square, halfmove clock, fullmove number, score and result
+#[derive(Debug, Clone)]
+pub struct PackedBoard {
+    pub data: [u8; PACKED_BOARD_SIZE],
+}
+
+impl PackedBoard {
+    pub fn pack_entry(entry: &TrainingDataEntry, options: &ExportOptions) -> Self {
+        let mut packed = Self::pack_position(&options.orient(&entry.pos));
+
+        packed.data[28..30].copy_from_slice(&options.score(entry).to_le_bytes());
+        packed.data[30] = options.wdl(entry);
+
+        packed
+    }
+
+    /// Pack a position without a score and result
+    pub fn pack_position(pos: &Position) -> Self {
+        let mut data = [0; PACKED_BOARD_SIZE];
+
+        data[..8].copy_from_slice(&pos.occupied().bits().to_le_bytes());
+
+        for (i, sq) in pos.occupied().iter().enumerate() {
+            let piece = pos.piece_at(sq);
+            let can_castle = CASTLING_ROOKS.iter().any(|&(rights, rook, rook_sq)| {
+                piece == rook && sq == rook_sq && pos.castling_rights().contains(rights)
+            });
+
+            let piece_type = if can_castle {
+                UNMOVED_ROOK
+            } else {
+                piece.piece_type().ordinal()
+            };
+
+            data[8 + i / 2] |= (piece.color().ordinal() << 3 | piece_type) << (4 * (i % 2));
+        }
+
+        // Square::NONE has the index 64 which is also used by marlinformat
+        data[24] = pos.side_to_move().ordinal() << 7 | pos.ep_square().index() as u8;
+        data[25] = pos.rule50_counter().min(u8::MAX as u16) as u8;
+        data[26..28].copy_from_slice(&(pos.ply() / 2 + 1).to_le_bytes());
+
+        Self { data }
+    }
+
+    /// Returns `None` if the board can't be decoded
+    pub fn unpack_position(&self) -> Option<Position> {
+        let occupied = Bitboard::new(u64::from_le_bytes(self.data[..8].try_into().unwrap()));
+        if occupied.count() > 32 {
+            return None;
+        }
+
+        let mut pos = Position::new();
+
+        for (i, sq) in occupied.iter().enumerate() {
+            let value = self.data[8 + i / 2] >> (4 * (i % 2)) & 0xF;
+            let color = Color::from_ordinal(value >> 3);
+
+            let piece = match value & 7 {
+                UNMOVED_ROOK => {
+                    let &(rights, rook, _) = CASTLING_ROOKS
+                        .iter()
+                        .find(|&&(_, rook, rook_sq)| rook.color() == color && rook_sq == sq)?;
+
+                    pos.add_castling_rights(rights);
+                    rook
+                }
+                piece_type @ 0..=5 => Piece::new(PieceType::from_ordinal(piece_type), color),
+                _ => return None,
+            };
+
+            pos.place(piece, sq);
+        }
+
+        for color in [Color::White, Color::Black] {
+            if pos.pieces_bb_color(color, PieceType::King).count() != 1 {
+                return None;
+            }
+        }
+
+        let pawns = pos.pieces_bb_color(Color::White, PieceType::Pawn)
+            | pos.pieces_bb_color(Color::Black, PieceType::Pawn);
+        if pawns.bits() & 0xFF00_0000_0000_00FF != 0 {
+            return None;
+        }
+
+        let stm = Color::from_ordinal(self.data[24] >> 7);
+        let ep = (self.data[24] & 0x7F) as u32;
+        if ep > 64 {
+            return None;
+        }
+
+        if ep != 64 {
+            // the pawn that just moved two squares has to be in front of it
+            let ep = Square::new(ep);
+            let ep_rank = if stm == Color::White { 5 } else { 2 };
+
+            if ep.index() / 8 != ep_rank
+                || pos.piece_at(Square::new(ep.index() ^ 8)) != Piece::new(PieceType::Pawn, !stm)
+            {
+                return None;
+            }
+
+            pos.set_ep_square_unchecked(ep);
+        }
+
+        let fullmove = u16::from_le_bytes([self.data[26], self.data[27]]);
+
+        pos.set_side_to_move(stm);
+        pos.set_rule50_counter(self.data[25] as u16);
+        pos.set_ply(fullmove.max(1).saturating_sub(1).saturating_mul(2));
+
+        Some(pos)
+    }
+}
+
+/// Convert a binpack to a marlinformat file, returns the number of entries
+pub fn binpack_to_marlin(input: &str, output: &str, options: &ExportOptions) -> Result<u64> {
+    export_binpack(input, output, |entry| {
+        PackedBoard::pack_entry(entry, options).data
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::{export::WdlMapping, test_util::entry};
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_pack_startpos() {
+        let startpos = entry(
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            30,
+            0,
+        );
+        let board = PackedBoard::pack_entry(&startpos, &ExportOptions::default());
+
+        assert_eq!(board.data[..8], 0xFFFF_0000_0000_FFFFu64.to_le_bytes());
+        // both rooks can castle
+        assert_eq!(board.data[8..12], [0x16, 0x42, 0x25, 0x61]);
+        assert_eq!(board.data[20..24], [0x9E, 0xCA, 0xAD, 0xE9]);
+        assert_eq!(board.data[24..], [64, 0, 1, 0, 30, 0, 1, 0]);
+    }
+
+    #[test]
+    fn test_pack_black_to_move() {
+        let black = entry("r3k2r/8/8/8/3pP3/8/8/4K2R b Kq e3 5 40", -50, 1);
+
+        let board = PackedBoard::pack_entry(&black, &ExportOptions::default());
+        // the rooks on h1 and a8 can castle, the one on h8 can't
+        assert_eq!(board.data[8..12], [0x65, 0x08, 0xDE, 0x0B]);
+        assert_eq!(board.data[24..], [0x80 | 20, 5, 40, 0, 50, 0, 0, 0]);
+
+        let options = ExportOptions {
+            stm_perspective: true,
+            wdl: WdlMapping::Score { threshold: 40 },
+            ..Default::default()
+        };
+        let flipped = PackedBoard::pack_entry(&black, &options);
+        let occupied = black.pos.occupied().bits().swap_bytes();
+        assert_eq!(flipped.data[..8], occupied.to_le_bytes());
+        assert_eq!(flipped.data[8], 0x56);
+        assert_eq!(flipped.data[24..], [44, 5, 40, 0, 0xCE, 0xFF, 0, 0]);
+    }
+
+    #[test]
+    fn test_unpack_position() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/8/8/8/3pP3/8/8/4K2R b Kq e3 5 40",
+            "8/2P5/8/8/8/2k5/8/K7 w - - 99 300",
+        ];
+
+        for fen in fens {
+            let pos = Position::from_fen(fen).unwrap();
+            let unpacked = PackedBoard::pack_position(&pos).unpack_position().unwrap();
+            assert_eq!(unpacked.fen(), fen);
+        }
+
+        let mut board = PackedBoard::pack_position(&Position::from_fen(fens[0]).unwrap());
+        // a rook that can castle on b1
+        board.data[8] = 0x66;
+        assert!(board.unpack_position().is_none());
+
+        // no black king
+        board.data[8] = 0x16;
+        board.data[22] = 0xAA;
+        assert!(board.unpack_position().is_none());
+
+        let mut pos = Position::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        pos.place(Piece::WHITE_PAWN, Square::H8);
+        assert!(PackedBoard::pack_position(&pos).unpack_position().is_none());
+
+        // an en passant square without a pawn in front of it
+        let mut board = PackedBoard::pack_position(&Position::from_fen(fens[1]).unwrap());
+        board.data[24] = 0x80 | 21;
+        assert!(board.unpack_position().is_none());
+    }
+
+    #[test]
+    fn test_export_file() {
+        let out = NamedTempFile::new().unwrap();
+        let path = out.path().to_str().unwrap();
+
+        let count = binpack_to_marlin("./test/ep1.binpack", path, &ExportOptions::default());
+        assert_eq!(count.unwrap(), 3);
+        assert_eq!(std::fs::metadata(path).unwrap().len(), 3 * 32);
+    }
+}
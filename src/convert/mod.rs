@@ -0,0 +1,114 @@
+use std::io::{self, ErrorKind, Read};
+use thiserror::Error;
+
+use crate::{
+    binpack_error::BinpackError,
+    chess::color::Color,
+    reader::training_data_reader::{CompressedReaderError, CompressedTrainingDataEntryReader},
+    training_data_entry::TrainingDataEntry,
+    writer::training_data_writer::CompressedTrainingDataEntryWriter,
+};
+
+pub mod bin;
+pub mod bullet;
+pub mod export;
+pub mod marlin;
+pub mod pgn;
+pub mod plain;
+pub mod viri;
+
+#[cfg(test)]
+pub(crate) mod test_util;
+
+#[derive(Debug, Error)]
+pub enum ConvertError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Reader error: {0}")]
+    Reader(#[from] CompressedReaderError),
+    #[error("Binpack error: {0}")]
+    Binpack(#[from] BinpackError),
+    #[error("Line {line}: {message}")]
+    Parse { line: usize, message: String },
+    #[error("Entry {index}: {message}")]
+    InvalidEntry { index: u64, message: String },
+}
+
+pub type Result<T> = std::result::Result<T, ConvertError>;
+
+/// Fill `buf` from `input`, returns less than `buf.len()` bytes only at the end of the input
+pub(crate) fn read_record<R: Read>(input: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+
+    while len < buf.len() {
+        match input.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(len)
+}
+
+/// Binpack scores and results are relative to the side to move, negating
+/// them twice is lossless
+pub(crate) fn white_relative(stm: Color, value: i16) -> i16 {
+    match stm {
+        Color::White => value,
+        Color::Black => value.wrapping_neg(),
+    }
+}
+
+/// Call `f` with the entries of every chain of a binpack, the stem and the
+/// entries of its movetext. Like the moves of a game every entry is the
+/// continuation of the one before it, which is what the game formats expect.
+/// Returns the number of entries.
+pub(crate) fn for_each_game(
+    input: &str,
+    mut f: impl FnMut(&[TrainingDataEntry]) -> Result<()>,
+) -> Result<u64> {
+    let mut reader = CompressedTrainingDataEntryReader::new(input)?;
+    let mut game = Vec::new();
+    let mut count = 0;
+
+    while reader.has_next() {
+        let is_chain_start = reader.is_chain_start();
+        let entry = reader.next_entry()?;
+
+        if is_chain_start && !game.is_empty() {
+            f(&game)?;
+            game.clear();
+        }
+
+        game.push(entry);
+        count += 1;
+    }
+
+    if !game.is_empty() {
+        f(&game)?;
+    }
+
+    Ok(count)
+}
+
+/// Write the entries of every game to a binpack, returns the number of entries
+pub(crate) fn import_binpack<G: IntoIterator<Item = TrainingDataEntry>>(
+    output: &str,
+    append: bool,
+    games: impl Iterator<Item = Result<G>>,
+) -> Result<u64> {
+    let mut writer = CompressedTrainingDataEntryWriter::new(output, append)?;
+    let mut count = 0;
+
+    for game in games {
+        for entry in game? {
+            writer.write_entry(&entry)?;
+            count += 1;
+        }
+    }
+
+    writer.flush()?;
+    Ok(count)
+}
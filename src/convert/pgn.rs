@@ -0,0 +1,743 @@
+//! PGN export and import of binpack games.
+//!
+//! Exported games start at the position of their first entry, which is given
+//! by the `FEN` tag, and have the score of every move as a comment. The
+//! scores are relative to the side to move like in binpacks.
+//!
+//! Imported games take their scores from `[%eval]` comments, which hold the
+//! evaluation of the position after a move from white's point of view.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::iter::FusedIterator;
+
+use crate::{
+    chess::{color::Color, position::Position, r#move::Move},
+    reader::skip_filter::VALUE_NONE,
+    training_data_entry::TrainingDataEntry,
+};
+
+use super::{for_each_game, import_binpack, white_relative, ConvertError, Result};
+
+const MAX_LINE_LENGTH: usize = 79;
+
+/// Write the entries of one game, e.g. a chain of a binpack
+pub fn write_pgn_game<W: Write>(out: &mut W, entries: &[TrainingDataEntry]) -> io::Result<()> {
+    let Some(first) = entries.first() else {
+        return Ok(());
+    };
+
+    let result = match white_relative(first.pos.side_to_move(), first.result) {
+        1 => "1-0",
+        -1 => "0-1",
+        _ => "1/2-1/2",
+    };
+
+    let fen = first.pos.fen();
+    let tags = [
+        ("Event", "?"),
+        ("Site", "?"),
+        ("Date", "????.??.??"),
+        ("Round", "?"),
+        ("White", "?"),
+        ("Black", "?"),
+        ("Result", result),
+        ("SetUp", "1"),
+        ("FEN", &fen),
+    ];
+
+    for (tag, value) in tags {
+        writeln!(out, "[{tag} \"{value}\"]")?;
+    }
+
+    writeln!(out)?;
+
+    let mut tokens = Vec::new();
+
+    for (i, entry) in entries.iter().enumerate() {
+        let fullmove = entry.pos.ply() / 2 + 1;
+
+        if entry.pos.side_to_move() == Color::White {
+            tokens.push(format!("{fullmove}."));
+        } else if i == 0 {
+            tokens.push(format!("{fullmove}..."));
+        }
+
+        tokens.push(entry.mv.as_san(&entry.pos));
+        tokens.push(format!("{{{}}}", entry.score));
+    }
+
+    tokens.push(result.to_string());
+
+    let mut line = String::new();
+
+    for token in tokens {
+        if !line.is_empty() && line.len() + 1 + token.len() > MAX_LINE_LENGTH {
+            writeln!(out, "{line}")?;
+            line.clear();
+        }
+
+        if !line.is_empty() {
+            line.push(' ');
+        }
+
+        line.push_str(&token);
+    }
+
+    writeln!(out, "{line}")?;
+    writeln!(out)
+}
+
+/// Convert a binpack to PGN, returns the number of games
+pub fn binpack_to_pgn(input: &str, output: &str) -> Result<u64> {
+    let mut out = BufWriter::new(File::create(output)?);
+    let mut num_games = 0;
+
+    for_each_game(input, |game| {
+        num_games += 1;
+        Ok(write_pgn_game(&mut out, game)?)
+    })?;
+
+    out.flush()?;
+    Ok(num_games)
+}
+
+/// Score of a mate in 0 plies, like in Stockfish
+const VALUE_MATE: i32 = 32000;
+
+const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PgnImportOptions {
+    /// Moves played at a lower ply aren't written
+    pub min_ply: u16,
+    /// Skip moves with a `book` comment, like cutechess marks opening moves
+    pub skip_book: bool,
+    /// Skip games with an unknown result, otherwise they count as draws
+    pub skip_unknown_result: bool,
+}
+
+impl Default for PgnImportOptions {
+    fn default() -> Self {
+        Self {
+            min_ply: 0,
+            skip_book: false,
+            skip_unknown_result: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PgnMove {
+    pub mv: Move,
+    /// Evaluation of the position after the move from white's point of view
+    pub eval: Option<i16>,
+    pub is_book: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    pub start: Position,
+    pub moves: Vec<PgnMove>,
+    /// Result from white's point of view, `None` if it is unknown
+    pub result: Option<i16>,
+}
+
+impl PgnGame {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The entries of the game, moves without an evaluation of the position
+    /// before them get a score of [`VALUE_NONE`]
+    pub fn entries(&self, options: &PgnImportOptions) -> Vec<TrainingDataEntry> {
+        if self.result.is_none() && options.skip_unknown_result {
+            return Vec::new();
+        }
+
+        let result = self.result.unwrap_or(0);
+        let mut pos = self.start;
+        // the start position has no evaluation
+        let mut eval = None;
+        let mut entries = Vec::new();
+
+        for mv in &self.moves {
+            let stm = pos.side_to_move();
+
+            if pos.ply() >= options.min_ply && !(options.skip_book && mv.is_book) {
+                entries.push(TrainingDataEntry {
+                    pos,
+                    mv: mv.mv,
+                    score: eval.map_or(VALUE_NONE, |eval| white_relative(stm, eval)),
+                    ply: pos.ply(),
+                    result: white_relative(stm, result),
+                });
+            }
+
+            eval = mv.eval;
+            pos = pos.after_move(mv.mv);
+        }
+
+        entries
+    }
+}
+
+/// Reads games from PGN text. Variations, NAGs and comments other than
+/// evaluations and book marks are ignored.
+#[derive(Debug)]
+pub struct PgnReader<R> {
+    input: R,
+    line_number: usize,
+    pending_line: Option<String>,
+    // set after an error, true once the movetext of the broken game is reached
+    skipping: Option<bool>,
+}
+
+impl<R: BufRead> PgnReader<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            input,
+            line_number: 0,
+            pending_line: None,
+            skipping: None,
+        }
+    }
+
+    /// Read the next game, returns `None` at the end of the input. After an
+    /// error the rest of the game is skipped, so reading can continue with
+    /// the next game.
+    pub fn next_game(&mut self) -> Result<Option<PgnGame>> {
+        if let Some(mut in_movetext) = self.skipping.take() {
+            while let Some(line) = self.next_line()? {
+                let is_tag = line.starts_with('[');
+
+                if is_tag && in_movetext {
+                    self.pending_line = Some(line);
+                    break;
+                }
+
+                in_movetext |= !is_tag && !line.is_empty();
+            }
+        }
+
+        let mut game = GameBuilder::default();
+
+        while let Some(line) = self.next_line()? {
+            let is_tag = line.starts_with('[') && game.comment.is_none() && game.depth == 0;
+
+            if is_tag && game.pos.is_some() {
+                self.pending_line = Some(line);
+                break;
+            }
+
+            let parsed = if is_tag {
+                game.add_tag(&line)
+            } else if line.starts_with('%') {
+                Ok(())
+            } else {
+                game.add_movetext(&line)
+            };
+
+            if let Err(message) = parsed {
+                self.skipping = Some(!is_tag);
+                return Err(ConvertError::Parse {
+                    line: self.line_number,
+                    message,
+                });
+            }
+
+            if game.termination.is_some() {
+                break;
+            }
+        }
+
+        if game.tags.is_empty() && game.pos.is_none() {
+            return Ok(None);
+        }
+
+        game.finish()
+            .map(Some)
+            .map_err(|message| ConvertError::Parse {
+                line: self.line_number,
+                message,
+            })
+    }
+
+    fn next_line(&mut self) -> Result<Option<String>> {
+        if let Some(line) = self.pending_line.take() {
+            return Ok(Some(line));
+        }
+
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        self.line_number += 1;
+        Ok(Some(line.trim().to_string()))
+    }
+}
+
+impl<R: BufRead> Iterator for PgnReader<R> {
+    type Item = Result<PgnGame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_game().transpose()
+    }
+}
+
+impl<R: BufRead> FusedIterator for PgnReader<R> {}
+
+#[derive(Debug, Default)]
+struct GameBuilder {
+    tags: Vec<(String, String)>,
+    // start and current position, known once the movetext starts
+    pos: Option<(Position, Position)>,
+    moves: Vec<PgnMove>,
+    comment: Option<String>,
+    depth: usize,
+    termination: Option<String>,
+}
+
+impl GameBuilder {
+    fn add_tag(&mut self, line: &str) -> std::result::Result<(), String> {
+        let tag = line
+            .strip_prefix('[')
+            .and_then(|tag| tag.strip_suffix(']'))
+            .ok_or_else(|| format!("Invalid tag {line}"))?;
+
+        let (name, value) = tag
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| format!("Invalid tag {line}"))?;
+
+        let value = value
+            .trim()
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .ok_or_else(|| format!("Invalid tag {line}"))?;
+
+        let value = value.replace("\\\"", "\"").replace("\\\\", "\\");
+        self.tags.push((name.to_string(), value));
+
+        Ok(())
+    }
+
+    fn add_movetext(&mut self, line: &str) -> std::result::Result<(), String> {
+        let mut rest = line;
+
+        while !rest.is_empty() && self.termination.is_none() {
+            if let Some(comment) = &mut self.comment {
+                let Some(end) = rest.find('}') else {
+                    comment.push_str(rest);
+                    comment.push(' ');
+                    return Ok(());
+                };
+
+                comment.push_str(&rest[..end]);
+                rest = &rest[end + 1..];
+
+                let comment = self.comment.take().unwrap();
+                if self.depth == 0 {
+                    self.add_comment(&comment)?;
+                }
+
+                continue;
+            }
+
+            rest = rest.trim_start();
+
+            let Some(c) = rest.chars().next() else {
+                break;
+            };
+
+            match c {
+                '{' => self.comment = Some(String::new()),
+                '(' => self.depth += 1,
+                ')' => self.depth = self.depth.saturating_sub(1),
+                // the rest of the line is a comment
+                ';' => return Ok(()),
+                _ => {
+                    let len = rest
+                        .find(|c: char| c.is_whitespace() || "{}();".contains(c))
+                        .unwrap_or(rest.len());
+
+                    if self.depth == 0 {
+                        self.add_token(&rest[..len])?;
+                    }
+
+                    rest = &rest[len..];
+                    continue;
+                }
+            }
+
+            rest = &rest[1..];
+        }
+
+        Ok(())
+    }
+
+    fn add_token(&mut self, token: &str) -> std::result::Result<(), String> {
+        if matches!(token, "1-0" | "0-1" | "1/2-1/2" | "*") {
+            self.termination = Some(token.to_string());
+            return Ok(());
+        }
+
+        // move numbers can be followed by the move without a space
+        let token = token.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+
+        if token.is_empty() || token.starts_with('$') {
+            return Ok(());
+        }
+
+        let (_, pos) = self.position()?;
+        let mv = Move::from_san(&pos, token).ok_or_else(|| format!("Illegal move {token}"))?;
+
+        self.moves.push(PgnMove {
+            mv,
+            eval: None,
+            is_book: false,
+        });
+
+        let (start, _) = self.pos.unwrap();
+        self.pos = Some((start, pos.after_move(mv)));
+
+        Ok(())
+    }
+
+    fn add_comment(&mut self, comment: &str) -> std::result::Result<(), String> {
+        let Some((_, pos)) = self.pos else {
+            return Ok(());
+        };
+
+        let Some(last) = self.moves.last_mut() else {
+            return Ok(());
+        };
+
+        if comment.trim() == "book" {
+            last.is_book = true;
+        }
+
+        if let Some(eval) = comment.split("[%eval").nth(1) {
+            let eval = eval.split(']').next().unwrap_or("").trim();
+            let eval = eval.split(',').next().unwrap_or("").trim();
+
+            last.eval = Some(
+                parse_eval(eval, pos.side_to_move())
+                    .ok_or_else(|| format!("Invalid evaluation {eval}"))?,
+            );
+        }
+
+        Ok(())
+    }
+
+    fn position(&mut self) -> std::result::Result<(Position, Position), String> {
+        if self.pos.is_none() {
+            let fen = self
+                .tags
+                .iter()
+                .find(|(tag, _)| tag == "FEN")
+                .map_or(START_FEN, |(_, fen)| fen.as_str());
+
+            let start = Position::from_fen(fen).map_err(|e| format!("Invalid fen {fen}: {e}"))?;
+            self.pos = Some((start, start));
+        }
+
+        Ok(self.pos.unwrap())
+    }
+
+    fn finish(mut self) -> std::result::Result<PgnGame, String> {
+        let (start, _) = self.position()?;
+
+        let result = self
+            .tags
+            .iter()
+            .find(|(tag, _)| tag == "Result")
+            .map(|(_, result)| result.as_str())
+            .or(self.termination.as_deref());
+
+        let result = match result {
+            Some("1-0") => Some(1),
+            Some("0-1") => Some(-1),
+            Some("1/2-1/2") => Some(0),
+            _ => None,
+        };
+
+        Ok(PgnGame {
+            tags: self.tags,
+            start,
+            moves: self.moves,
+            result,
+        })
+    }
+}
+
+/// Parse an evaluation in pawns like `0.35` or a mate like `#-3` from
+/// white's point of view, `stm` is the side to move in the evaluated position
+fn parse_eval(eval: &str, stm: Color) -> Option<i16> {
+    if let Some(mate) = eval.strip_prefix('#') {
+        let moves = mate.parse::<i32>().ok()?;
+        let white_mates = moves > 0 || (moves == 0 && stm == Color::Black);
+        let mating_side_to_move = white_mates == (stm == Color::White);
+
+        let plies = 2 * moves.abs() - mating_side_to_move as i32;
+        let score = VALUE_MATE - plies.max(0);
+
+        return Some(if white_mates { score } else { -score } as i16);
+    }
+
+    let pawns = eval.parse::<f64>().ok().filter(|pawns| pawns.is_finite())?;
+    let max = (VALUE_MATE - 1) as f64;
+
+    Some((pawns * 100.0).round().clamp(-max, max) as i16)
+}
+
+/// Convert PGN to a binpack, returns the number of entries
+pub fn pgn_to_binpack(
+    input: &str,
+    output: &str,
+    append: bool,
+    options: &PgnImportOptions,
+) -> Result<u64> {
+    let reader = PgnReader::new(BufReader::new(File::open(input)?));
+    import_binpack(
+        output,
+        append,
+        reader.map(|game| game.map(|game| game.entries(options))),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        convert::test_util::play, reader::training_data_reader::CompressedTrainingDataEntryReader,
+        writer::training_data_writer::CompressedTrainingDataEntryWriter,
+    };
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_write_game() {
+        let fen = "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 2 3";
+        let game = play(fen, &["g8f6", "f3e5", "c6e5", "d2d4"], -1);
+
+        let mut pgn = Vec::new();
+        write_pgn_game(&mut pgn, &game).unwrap();
+
+        let expected = format!(
+            "[Event \"?\"]\n\
+             [Site \"?\"]\n\
+             [Date \"????.??.??\"]\n\
+             [Round \"?\"]\n\
+             [White \"?\"]\n\
+             [Black \"?\"]\n\
+             [Result \"1-0\"]\n\
+             [SetUp \"1\"]\n\
+             [FEN \"{fen}\"]\n\
+             \n\
+             3... Nf6 {{0}} 4. Nxe5 {{1}} Nxe5 {{2}} 5. d4 {{3}} 1-0\n\
+             \n"
+        );
+
+        assert_eq!(String::from_utf8(pgn).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_long_lines_are_wrapped() {
+        let moves = ["g1f3", "g8f6", "f3g1", "f6g8"].repeat(10);
+        let game = play(
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            &moves,
+            0,
+        );
+
+        let mut pgn = Vec::new();
+        write_pgn_game(&mut pgn, &game).unwrap();
+        let pgn = String::from_utf8(pgn).unwrap();
+
+        let movetext = pgn.split("\n\n").nth(1).unwrap();
+        assert!(movetext.lines().count() > 1);
+        assert!(movetext.lines().all(|line| line.len() <= MAX_LINE_LENGTH));
+        assert!(movetext.starts_with("1. Nf3 {0} Nf6 {1} 2. Ng1 {2}"));
+        assert!(movetext.ends_with("1/2-1/2"));
+    }
+
+    #[test]
+    fn test_binpack_to_pgn() {
+        let out = NamedTempFile::new().unwrap();
+        let path = out.path().to_str().unwrap();
+
+        let num_games = binpack_to_pgn("./test/ep1.binpack", path).unwrap();
+        let pgn = std::fs::read_to_string(path).unwrap();
+
+        assert_eq!(pgn.matches("[FEN ").count() as u64, num_games);
+        assert_eq!(pgn.matches('{').count(), 3);
+    }
+
+    #[test]
+    fn test_binpack_to_pgn_keeps_chains() {
+        let game = play(
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            &["e2e4", "e7e5", "g1f3", "b8c6"],
+            1,
+        );
+
+        // the second half continues the first one but is a chain of its own
+        let binpack = NamedTempFile::new().unwrap();
+        let binpack_path = binpack.path().to_str().unwrap();
+        let mut writer = CompressedTrainingDataEntryWriter::new(binpack_path, false).unwrap();
+        for (i, entry) in game.iter().enumerate() {
+            if i == 2 {
+                writer.flush().unwrap();
+            }
+            writer.write_entry(entry).unwrap();
+        }
+        drop(writer);
+
+        let out = NamedTempFile::new().unwrap();
+        let path = out.path().to_str().unwrap();
+        assert_eq!(binpack_to_pgn(binpack_path, path).unwrap(), 2);
+
+        let games = read_games(&std::fs::read_to_string(path).unwrap());
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[1].as_ref().unwrap().start, game[2].pos);
+    }
+
+    fn read_games(pgn: &str) -> Vec<Result<PgnGame>> {
+        PgnReader::new(pgn.as_bytes()).collect()
+    }
+
+    #[test]
+    fn test_read_game() {
+        let pgn = "[Event \"Test \\\"match\\\"\"]\n\
+                   [Result \"0-1\"]\n\
+                   \n\
+                   1. e4 {+0.35/12 0.1s} e5 {[%eval -0.2,10] [%clk 0:01:00]}\n\
+                   2. Nf3 $1 (2. f4 exf4 {[%eval 1.0]}) Nc6 ; a comment {\n\
+                   3.Bb5 {a long\n\
+                   comment [%eval #-2]} 0-1\n";
+
+        let games = read_games(pgn);
+        assert_eq!(games.len(), 1);
+
+        let game = games[0].as_ref().unwrap();
+        assert_eq!(game.tag("Event"), Some("Test \"match\""));
+        assert_eq!(game.result, Some(-1));
+
+        let moves = game.moves.iter().map(|m| m.mv.as_uci()).collect::<Vec<_>>();
+        assert_eq!(moves, ["e2e4", "e7e5", "g1f3", "b8c6", "f1b5"]);
+
+        let evals = game.moves.iter().map(|m| m.eval).collect::<Vec<_>>();
+        // black mates in 2 with white to move after Bb5, 3 plies
+        assert_eq!(evals, [None, Some(-20), None, None, Some(-31997)]);
+    }
+
+    #[test]
+    fn test_entries() {
+        let pgn = "[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 10\"]\n\
+                   \n\
+                   10... Kd7 {book} 11. e4 {book} Kc6 {[%eval 1.5]} 12. e5 {[%eval 2.0]} *\n";
+
+        let game = read_games(pgn).pop().unwrap().unwrap();
+        assert_eq!(game.result, None);
+        assert_eq!(game.start.fen(), "4k3/8/8/8/8/8/4P3/4K3 b - - 0 10");
+
+        assert!(game.entries(&PgnImportOptions::default()).is_empty());
+
+        let options = PgnImportOptions {
+            skip_unknown_result: false,
+            ..Default::default()
+        };
+        let entries = game.entries(&options);
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0].ply, 19);
+        assert!(entries.iter().all(|e| e.result == 0));
+
+        let scores = entries.iter().map(|e| e.score).collect::<Vec<_>>();
+        assert_eq!(scores, [VALUE_NONE, VALUE_NONE, VALUE_NONE, 150]);
+
+        let options = PgnImportOptions {
+            min_ply: 22,
+            skip_book: true,
+            skip_unknown_result: false,
+        };
+        let entries = game.entries(&options);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].mv.as_uci(), "e4e5");
+        assert_eq!(entries[0].score, 150);
+    }
+
+    #[test]
+    fn test_parse_eval() {
+        assert_eq!(parse_eval("0.35", Color::White), Some(35));
+        assert_eq!(parse_eval("-12.345", Color::Black), Some(-1235));
+        assert_eq!(parse_eval("1000", Color::White), Some(31999));
+        assert_eq!(parse_eval("#1", Color::White), Some(31999));
+        assert_eq!(parse_eval("#1", Color::Black), Some(31998));
+        assert_eq!(parse_eval("#-0", Color::White), Some(-32000));
+        assert_eq!(parse_eval("+M3", Color::White), None);
+        assert_eq!(parse_eval("nan", Color::White), None);
+    }
+
+    #[test]
+    fn test_error_recovery() {
+        let pgn = "[Event \"1\"]\n\
+                   \n\
+                   1. e4 e5 2. Ke3 Nc6 1-0\n\
+                   \n\
+                   [Event \"2\"\n\
+                   [Site \"?\"]\n\
+                   \n\
+                   1. d4 0-1\n\
+                   \n\
+                   [Event \"3\"]\n\
+                   \n\
+                   1. c4 1/2-1/2\n";
+
+        let games = read_games(pgn);
+        assert_eq!(games.len(), 3);
+
+        assert!(matches!(games[0], Err(ConvertError::Parse { line: 3, .. })));
+        assert!(matches!(games[1], Err(ConvertError::Parse { line: 5, .. })));
+
+        let game = games[2].as_ref().unwrap();
+        assert_eq!(game.tag("Event"), Some("3"));
+        assert_eq!(game.moves.len(), 1);
+        assert_eq!(game.result, Some(0));
+    }
+
+    #[test]
+    fn test_pgn_to_binpack() {
+        let pgn = NamedTempFile::new().unwrap();
+        let binpack = NamedTempFile::new().unwrap();
+        let pgn_path = pgn.path().to_str().unwrap();
+        let binpack_path = binpack.path().to_str().unwrap();
+
+        std::fs::write(
+            pgn_path,
+            "[Result \"1-0\"]\n\n1. e4 {[%eval 0.3]} c5 {[%eval 0.4]} 2. Nf3 1-0\n\n\
+             [Result \"*\"]\n\n1. d4 *\n",
+        )
+        .unwrap();
+
+        let count = pgn_to_binpack(pgn_path, binpack_path, false, &PgnImportOptions::default());
+        assert_eq!(count.unwrap(), 3);
+
+        let entries = CompressedTrainingDataEntryReader::new(binpack_path)
+            .unwrap()
+            .map(|e| e.unwrap())
+            .collect::<Vec<_>>();
+
+        let moves = entries.iter().map(|e| e.mv.as_uci()).collect::<Vec<_>>();
+        assert_eq!(moves, ["e2e4", "c7c5", "g1f3"]);
+
+        let scores = entries.iter().map(|e| e.score).collect::<Vec<_>>();
+        assert_eq!(scores, [VALUE_NONE, -30, 40]);
+
+        let results = entries.iter().map(|e| e.result).collect::<Vec<_>>();
+        assert_eq!(results, [1, -1, 1]);
+    }
+}
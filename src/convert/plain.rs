@@ -0,0 +1,263 @@
+//! Conversion between binpacks and the Stockfish `.plain` text format,
+//! every entry is a block of `key value` lines terminated by `e`.
+//!
+//! ```text
+//! fen rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1
+//! move e2e4
+//! score 30
+//! ply 0
+//! result 0
+//! e
+//! ```
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::iter::FusedIterator;
+use std::str::FromStr;
+
+use crate::{
+    chess::{position::Position, r#move::Move},
+    reader::training_data_reader::CompressedTrainingDataEntryReader,
+    training_data_entry::{TrainingDataEntry, MAX_PLY},
+};
+
+use super::{import_binpack, ConvertError, Result};
+
+pub fn write_plain_entry<W: Write>(out: &mut W, entry: &TrainingDataEntry) -> io::Result<()> {
+    writeln!(out, "fen {}", entry.pos.fen())?;
+    writeln!(out, "move {}", entry.mv.as_uci())?;
+    writeln!(out, "score {}", entry.score)?;
+    writeln!(out, "ply {}", entry.ply)?;
+    writeln!(out, "result {}", entry.result)?;
+    writeln!(out, "e")
+}
+
+/// Reads entries from `.plain` text. Every entry needs a `fen` and a `move`,
+/// the other values default to 0 if they are missing.
+#[derive(Debug)]
+pub struct PlainReader<R> {
+    input: R,
+    line: String,
+    line_number: usize,
+}
+
+impl<R: BufRead> PlainReader<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            input,
+            line: String::new(),
+            line_number: 0,
+        }
+    }
+
+    /// Read the next entry, returns `None` at the end of the input
+    pub fn next_entry(&mut self) -> Result<Option<TrainingDataEntry>> {
+        let mut pos = None;
+        let mut mv = None;
+        let mut score = 0;
+        let mut ply = 0;
+        let mut result = 0;
+        let mut is_empty = true;
+
+        loop {
+            self.line.clear();
+
+            if self.input.read_line(&mut self.line)? == 0 {
+                if is_empty {
+                    return Ok(None);
+                }
+
+                return Err(self.error("Entry is not terminated by 'e'"));
+            }
+
+            self.line_number += 1;
+
+            let line = self.line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            is_empty = false;
+
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            let value = value.trim();
+
+            match key {
+                "fen" => {
+                    pos = Some(
+                        Position::from_fen(value)
+                            .map_err(|e| self.error(&format!("Invalid fen {value}: {e}")))?,
+                    );
+                }
+                "move" => mv = Some(value.to_string()),
+                "score" => score = self.parse(value)?,
+                "ply" => ply = self.parse(value)?,
+                "result" => result = self.parse(value)?,
+                "e" => {
+                    let pos = pos.ok_or_else(|| self.error("Entry without fen"))?;
+                    let mv = mv.ok_or_else(|| self.error("Entry without move"))?;
+                    let mv = Move::from_uci(&pos, &mv)
+                        .filter(|&mv| pos.is_legal(mv))
+                        .ok_or_else(|| self.error(&format!("Illegal move {mv}")))?;
+
+                    if !(-1..=1).contains(&result) {
+                        return Err(self.error(&format!("Invalid result {result}")));
+                    }
+
+                    if ply > MAX_PLY {
+                        return Err(self.error(&format!("Ply {ply} is larger than {MAX_PLY}")));
+                    }
+
+                    return Ok(Some(TrainingDataEntry {
+                        pos,
+                        mv,
+                        score,
+                        ply,
+                        result,
+                    }));
+                }
+                _ => return Err(self.error(&format!("Unknown key {key}"))),
+            }
+        }
+    }
+
+    fn parse<T: FromStr>(&self, value: &str) -> Result<T> {
+        value
+            .parse()
+            .map_err(|_| self.error(&format!("Invalid number {value}")))
+    }
+
+    fn error(&self, message: &str) -> ConvertError {
+        ConvertError::Parse {
+            line: self.line_number,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for PlainReader<R> {
+    type Item = Result<TrainingDataEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().transpose()
+    }
+}
+
+impl<R: BufRead> FusedIterator for PlainReader<R> {}
+
+/// Convert a binpack to a `.plain` file, returns the number of entries
+pub fn binpack_to_plain(input: &str, output: &str) -> Result<u64> {
+    let reader = CompressedTrainingDataEntryReader::new(input)?;
+    let mut out = BufWriter::new(File::create(output)?);
+    let mut count = 0;
+
+    for entry in reader {
+        write_plain_entry(&mut out, &entry?)?;
+        count += 1;
+    }
+
+    out.flush()?;
+    Ok(count)
+}
+
+/// Convert a `.plain` file to a binpack, returns the number of entries
+pub fn plain_to_binpack(input: &str, output: &str, append: bool) -> Result<u64> {
+    let reader = PlainReader::new(BufReader::new(File::open(input)?));
+    import_binpack(
+        output,
+        append,
+        reader.map(|entry| entry.map(std::iter::once)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::test_util::read_ep1;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_plain_roundtrip() {
+        let entries = read_ep1();
+
+        let mut text = Vec::new();
+        for entry in &entries {
+            write_plain_entry(&mut text, entry).unwrap();
+        }
+
+        let text = String::from_utf8(text).unwrap();
+        assert_eq!(text.lines().filter(|&l| l == "e").count(), entries.len());
+        assert!(text.starts_with(&format!("fen {}\n", entries[0].pos.fen())));
+
+        let parsed = PlainReader::new(text.as_bytes())
+            .map(|e| e.unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(parsed, entries);
+        for (a, b) in parsed.iter().zip(&entries) {
+            assert_eq!(a.pos.fen(), b.pos.fen());
+        }
+    }
+
+    #[test]
+    fn test_file_conversion_roundtrip() {
+        let plain = NamedTempFile::new().unwrap();
+        let binpack = NamedTempFile::new().unwrap();
+        let plain_path = plain.path().to_str().unwrap();
+        let binpack_path = binpack.path().to_str().unwrap();
+
+        assert_eq!(
+            binpack_to_plain("./test/ep1.binpack", plain_path).unwrap(),
+            3
+        );
+        assert_eq!(
+            plain_to_binpack(plain_path, binpack_path, false).unwrap(),
+            3
+        );
+
+        let expected = std::fs::read("./test/ep1.binpack").unwrap();
+        assert_eq!(std::fs::read(binpack_path).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_missing_values_default_to_zero() {
+        let text = "fen 4k3/8/8/8/8/8/8/4K3 w - - 0 1\nmove e1e2\n\ne\n";
+        let entry = PlainReader::new(text.as_bytes())
+            .next_entry()
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(entry.mv.as_uci(), "e1e2");
+        assert_eq!((entry.score, entry.ply, entry.result), (0, 0, 0));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let cases = [
+            ("move e2e4\ne\n", 2),
+            ("fen 4k3/8/8/8/8/8/8/4K3 w - - 0 1\ne\n", 2),
+            ("fen 4k3/8/8/8/8/8/8/4K3 w - - 0 1\nmove e2e4\ne\n", 3),
+            ("fen 4k3/8/8/8/8/8/8/4K3 w - - 0 1\nmove e1e3\ne\n", 3),
+            ("fen 4k3/8/8/8/8/8/8/4K3 w - - 0 1\nmove e1e8\ne\n", 3),
+            (
+                "fen 4k3/8/8/8/8/8/8/4K3 w - - 0 1\nmove e1e2\nresult 2\ne\n",
+                4,
+            ),
+            (
+                "fen 4k3/8/8/8/8/8/8/4K3 w - - 0 1\nmove e1e2\nply 16384\ne\n",
+                4,
+            ),
+            ("fen 4k3/8/8/8/8/8/8/4K3 w - -\nscore x\n", 2),
+            ("fen 4k3/8/8/8/8/8/8/4K3 w - - 0 1\nmove e1e2\n", 2),
+            ("fen invalid\n", 1),
+            ("foo 1\n", 1),
+        ];
+
+        for (text, expected_line) in cases {
+            match PlainReader::new(text.as_bytes()).next_entry() {
+                Err(ConvertError::Parse { line, .. }) => assert_eq!(line, expected_line, "{text}"),
+                other => panic!("Expected parse error for {text:?}, got {other:?}"),
+            }
+        }
+    }
+}
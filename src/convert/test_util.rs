@@ -0,0 +1,49 @@
+//! Entries shared by the tests of the converters
+
+use crate::{
+    chess::{position::Position, r#move::Move},
+    reader::training_data_reader::CompressedTrainingDataEntryReader,
+    training_data_entry::TrainingDataEntry,
+};
+
+pub(crate) fn read_ep1() -> Vec<TrainingDataEntry> {
+    CompressedTrainingDataEntryReader::new("./test/ep1.binpack")
+        .unwrap()
+        .map(|e| e.unwrap())
+        .collect()
+}
+
+/// An entry without a move at ply 0
+pub(crate) fn entry(fen: &str, score: i16, result: i16) -> TrainingDataEntry {
+    TrainingDataEntry {
+        pos: Position::from_fen(fen).unwrap(),
+        mv: Move::null(),
+        score,
+        ply: 0,
+        result,
+    }
+}
+
+/// The entries of a game played from `fen`, every move is scored with its
+/// index and `result` is relative to the side to move of the first entry
+pub(crate) fn play(fen: &str, moves: &[&str], result: i16) -> Vec<TrainingDataEntry> {
+    let mut pos = Position::from_fen(fen).unwrap();
+    let mut result = result;
+    let mut entries = Vec::new();
+
+    for (score, uci) in (0..).zip(moves) {
+        let mv = Move::from_uci(&pos, uci).unwrap();
+        entries.push(TrainingDataEntry {
+            pos,
+            mv,
+            score,
+            ply: pos.ply(),
+            result,
+        });
+
+        pos = pos.after_move(mv);
+        result = -result;
+    }
+
+    entries
+}
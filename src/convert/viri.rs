@@ -0,0 +1,331 @@
+//! Conversion between binpacks and viriformat. A viriformat game is the
+//! marlinformat board of its first position followed by a 4 byte move and
+//! score for every ply and 4 zero bytes. The board holds the game result,
+//! the scores are relative to white.
+//!
+//! A game corresponds to a chain of a binpack, the stem and the entries of
+//! its movetext. Converting a binpack to viriformat clamps halfmove clocks
+//! above 255 and the plies are derived from the fullmove number of the board,
+//! everything else is kept.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::iter::FusedIterator;
+
+use crate::{
+    chess::{
+        coords::Square,
+        piece::Piece,
+        piecetype::PieceType,
+        position::Position,
+        r#move::{Move, MoveType},
+    },
+    training_data_entry::TrainingDataEntry,
+};
+
+use super::{
+    for_each_game, import_binpack,
+    marlin::{PackedBoard, PACKED_BOARD_SIZE},
+    read_record, white_relative, ConvertError, Result,
+};
+
+const EP_FLAG: u16 = 0b01 << 14;
+const CASTLE_FLAG: u16 = 0b10 << 14;
+const PROMO_FLAG: u16 = 0b11 << 14;
+
+/// Write the entries of one game, e.g. a chain of a binpack
+pub fn write_game<W: Write>(out: &mut W, entries: &[TrainingDataEntry]) -> io::Result<()> {
+    let Some(first) = entries.first() else {
+        return Ok(());
+    };
+
+    let mut board = PackedBoard::pack_position(&first.pos);
+    board.data[30] = (white_relative(first.pos.side_to_move(), first.result) + 1) as u8;
+    out.write_all(&board.data)?;
+
+    for entry in entries {
+        let score = white_relative(entry.pos.side_to_move(), entry.score);
+
+        out.write_all(&encode_move(entry.mv).to_le_bytes())?;
+        out.write_all(&score.to_le_bytes())?;
+    }
+
+    out.write_all(&[0; 4])
+}
+
+/// Viriformat moves store the origin in the lowest bits, castling is
+/// stored as the king capturing its own rook like in binpacks
+fn encode_move(mv: Move) -> u16 {
+    let data = (mv.from().index() | mv.to().index() << 6) as u16;
+
+    match mv.mtype() {
+        MoveType::Normal => data,
+        MoveType::Promotion => {
+            data | PROMO_FLAG | (mv.promoted_piece().piece_type().ordinal() as u16 - 1) << 12
+        }
+        MoveType::EnPassant => data | EP_FLAG,
+        MoveType::Castle => data | CASTLE_FLAG,
+    }
+}
+
+fn decode_move(pos: &Position, data: u16) -> Move {
+    let from = Square::new((data & 63) as u32);
+    let to = Square::new(((data >> 6) & 63) as u32);
+
+    match data & PROMO_FLAG {
+        EP_FLAG => Move::en_passant(from, to),
+        CASTLE_FLAG => Move::castle(from, to),
+        PROMO_FLAG => {
+            let piece_type = PieceType::from_ordinal(((data >> 12) & 3) as u8 + 1);
+            Move::promotion(from, to, Piece::new(piece_type, pos.side_to_move()))
+        }
+        _ => Move::normal(from, to),
+    }
+}
+
+/// Reads the games of viriformat data as the entries of every game
+#[derive(Debug)]
+pub struct ViriReader<R> {
+    input: R,
+    num_read: u64,
+}
+
+impl<R: Read> ViriReader<R> {
+    pub fn new(input: R) -> Self {
+        Self { input, num_read: 0 }
+    }
+
+    /// Read the entries of the next game, returns `None` at the end of the input
+    pub fn next_game(&mut self) -> Result<Option<Vec<TrainingDataEntry>>> {
+        let mut board = PackedBoard {
+            data: [0; PACKED_BOARD_SIZE],
+        };
+
+        let len = read_record(&mut self.input, &mut board.data)?;
+        if len == 0 {
+            return Ok(None);
+        }
+
+        let index = self.num_read;
+        self.num_read += 1;
+
+        let error = |message: &str| ConvertError::InvalidEntry {
+            index,
+            message: format!("Game {message}"),
+        };
+
+        if len < PACKED_BOARD_SIZE {
+            return Err(error("is truncated"));
+        }
+
+        let mut pos = board
+            .unpack_position()
+            .ok_or_else(|| error("has an invalid board"))?;
+
+        let white_result = match board.data[30] {
+            wdl @ 0..=2 => wdl as i16 - 1,
+            _ => return Err(error("has an invalid result")),
+        };
+
+        let mut next_ply = Some(pos.ply());
+        let mut entries = Vec::new();
+
+        loop {
+            let mut record = [0; 4];
+            if read_record(&mut self.input, &mut record)? < record.len() {
+                return Err(error("is not terminated"));
+            }
+
+            if record == [0; 4] {
+                return Ok(Some(entries));
+            }
+
+            let ply = next_ply.ok_or_else(|| error("has too many plies"))?;
+            let stm = pos.side_to_move();
+            let mv = decode_move(&pos, u16::from_le_bytes([record[0], record[1]]));
+
+            // also rejects flags that don't match the moved piece and squares
+            if !pos.is_legal(mv) {
+                return Err(error(&format!("has an illegal move at ply {ply}")));
+            }
+
+            entries.push(TrainingDataEntry {
+                pos,
+                mv,
+                score: white_relative(stm, i16::from_le_bytes([record[2], record[3]])),
+                ply,
+                result: white_relative(stm, white_result),
+            });
+
+            pos = pos.after_move(mv);
+            next_ply = ply.checked_add(1);
+        }
+    }
+}
+
+impl<R: Read> Iterator for ViriReader<R> {
+    type Item = Result<Vec<TrainingDataEntry>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_game().transpose()
+    }
+}
+
+impl<R: Read> FusedIterator for ViriReader<R> {}
+
+/// Convert a binpack to viriformat, every chain becomes a game.
+/// Returns the number of entries.
+pub fn binpack_to_viri(input: &str, output: &str) -> Result<u64> {
+    let mut out = BufWriter::new(File::create(output)?);
+    let count = for_each_game(input, |game| Ok(write_game(&mut out, game)?))?;
+
+    out.flush()?;
+    Ok(count)
+}
+
+/// Convert viriformat to a binpack, returns the number of entries
+pub fn viri_to_binpack(input: &str, output: &str, append: bool) -> Result<u64> {
+    let reader = ViriReader::new(BufReader::new(File::open(input)?));
+    import_binpack(output, append, reader)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::test_util::play;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_game_roundtrip() {
+        let game = play(
+            "r3k2r/1P6/8/3pP3/8/8/8/R3K2R w KQkq d6 0 30",
+            &["e5d6", "e8g8", "b7b8n", "f8d8", "e1g1"],
+            -1,
+        );
+
+        let mut data = Vec::new();
+        write_game(&mut data, &game).unwrap();
+        write_game(&mut data, &game[1..]).unwrap();
+        assert_eq!(data.len(), 2 * 32 + 11 * 4);
+
+        // en passant by white with a score of 0, then castling by
+        // black with a score of 1 for black
+        assert_eq!(data[32..36], [0xE4, 0x4A, 0x00, 0x00]);
+        assert_eq!(data[88..92], [0xFC, 0x8F, 0xFF, 0xFF]);
+
+        let games = ViriReader::new(data.as_slice())
+            .map(|g| g.unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(games, [game.clone(), game[1..].to_vec()]);
+        for (a, b) in games[0].iter().zip(&game) {
+            assert_eq!(a.pos.fen(), b.pos.fen());
+        }
+    }
+
+    #[test]
+    fn test_file_conversion_roundtrip() {
+        let viri = NamedTempFile::new().unwrap();
+        let binpack = NamedTempFile::new().unwrap();
+        let viri_path = viri.path().to_str().unwrap();
+        let binpack_path = binpack.path().to_str().unwrap();
+
+        assert_eq!(binpack_to_viri("./test/ep1.binpack", viri_path).unwrap(), 3);
+        assert_eq!(viri_to_binpack(viri_path, binpack_path, false).unwrap(), 3);
+
+        let expected = std::fs::read("./test/ep1.binpack").unwrap();
+        assert_eq!(std::fs::read(binpack_path).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_invalid_games() {
+        let game = play(
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            &["e2e4", "e7e5"],
+            0,
+        );
+
+        let mut data = Vec::new();
+        write_game(&mut data, &game).unwrap();
+
+        let unterminated = &data[..data.len() - 2];
+        assert!(ViriReader::new(unterminated).next_game().is_err());
+
+        let mut invalid_result = data.clone();
+        invalid_result[30] = 3;
+        assert!(ViriReader::new(invalid_result.as_slice())
+            .next_game()
+            .is_err());
+
+        // a black move for white
+        let mut invalid_move = data.clone();
+        invalid_move[32..34].copy_from_slice(&encode_move(game[1].mv).to_le_bytes());
+        assert!(matches!(
+            ViriReader::new(invalid_move.as_slice()).next_game(),
+            Err(ConvertError::InvalidEntry { index: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_ply_overflow() {
+        let game = play(
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            &["g1f3", "g8f6", "f3g1"],
+            0,
+        );
+
+        for (num_moves, is_valid) in [(2, true), (3, false)] {
+            let mut data = Vec::new();
+            write_game(&mut data, &game[..num_moves]).unwrap();
+            data[26..28].copy_from_slice(&32768u16.to_le_bytes());
+
+            match ViriReader::new(data.as_slice()).next_game() {
+                Ok(Some(entries)) if is_valid => {
+                    assert_eq!(entries.last().unwrap().ply, u16::MAX);
+                }
+                Err(ConvertError::InvalidEntry { index: 0, .. }) if !is_valid => (),
+                other => panic!("Unexpected result for {num_moves} moves: {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_move_flags() {
+        let game = play(
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            &["e2e4"],
+            0,
+        );
+
+        let mut data = Vec::new();
+        write_game(&mut data, &game).unwrap();
+
+        let g1f3 = encode_move(Move::normal(Square::G1, Square::new(21)));
+        let e2e4 = encode_move(Move::normal(Square::new(12), Square::new(28)));
+        let e1h1 = encode_move(Move::normal(Square::E1, Square::H1));
+
+        for mv in [
+            // en passant by a knight
+            g1f3 | EP_FLAG,
+            // en passant without an en passant square
+            e2e4 | EP_FLAG,
+            // castling with a pawn
+            e2e4 | CASTLE_FLAG,
+            // castling through the own pieces
+            e1h1 | CASTLE_FLAG,
+            // promotion on the fourth rank
+            e2e4 | PROMO_FLAG | 3 << 12,
+        ] {
+            let mut invalid = data.clone();
+            invalid[32..34].copy_from_slice(&mv.to_le_bytes());
+
+            assert!(
+                matches!(
+                    ViriReader::new(invalid.as_slice()).next_game(),
+                    Err(ConvertError::InvalidEntry { index: 0, .. })
+                ),
+                "{mv:#x}"
+            );
+        }
+    }
+}
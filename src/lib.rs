@@ -1,10 +1,12 @@
 mod arithmetic;
-mod chess;
 mod compressed_move;
-mod compressed_position;
+mod rng;
 mod training_data_file;
 
 pub mod binpack_error;
+pub mod chess;
+pub mod compressed_position;
+pub mod convert;
 pub mod reader;
 pub mod training_data_entry;
 pub mod writer;
@@ -16,7 +16,7 @@ fn main() {
     let t0 = std::time::Instant::now();
 
     while reader.has_next() {
-        let _entry = reader.next();
+        let _entry = reader.next_entry().unwrap();
 
         count += 1;
 
@@ -28,7 +28,7 @@ fn main() {
         // println!("result {}", entry.result);
         // println!("\n");
 
-        if count % 100000 == 0 {
+        if count.is_multiple_of(100000) {
             let percentage = reader.read_bytes() as f64 / reader.file_size() as f64 * 100.0;
 
             print_update(count, percentage, t0);
@@ -70,7 +70,7 @@ mod tests {
     //     let mut score: i64 = 0;
 
     //     while reader.has_next() {
-    //         let entry = reader.next();
+    //         let entry = reader.next_entry().unwrap();
 
     //         count += 1;
 
@@ -86,13 +86,13 @@ mod tests {
 
     #[test]
     fn test_reader_simple() {
-        let mut reader = CompressedTrainingDataEntryReader::new("./test/ep1.binpack").unwrap();
+        let reader = CompressedTrainingDataEntryReader::new("./test/ep1.binpack").unwrap();
 
         let mut count: u64 = 0;
         let mut score: i64 = 0;
 
-        while reader.has_next() {
-            let entry = reader.next();
+        for entry in reader {
+            let entry = entry.unwrap();
 
             count += 1;
 
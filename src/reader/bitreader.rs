@@ -1,22 +1,24 @@
-#[derive(Debug)]
-pub struct BitReader<'a> {
-    movetext: &'a [u8],
+use super::training_data_reader::{CompressedReaderError, Result};
+
+/// Reads bits from a movetext, the movetext itself is passed to every call
+/// so the reader only has to keep track of the read position.
+#[derive(Debug, Clone)]
+pub struct BitReader {
     read_bits_left: usize,
     read_offset: usize,
 }
 
-impl<'a> BitReader<'a> {
-    pub fn new(movetext: &'a [u8]) -> Self {
+impl BitReader {
+    pub fn new() -> Self {
         Self {
-            movetext,
             read_bits_left: 8,
             read_offset: 0,
         }
     }
 
-    pub fn extract_bits_le8(&mut self, count: usize) -> u8 {
+    pub fn extract_bits_le8(&mut self, movetext: &[u8], count: usize) -> Result<u8> {
         if count == 0 {
-            return 0;
+            return Ok(0);
         }
 
         if self.read_bits_left == 0 {
@@ -24,41 +26,62 @@ impl<'a> BitReader<'a> {
             self.read_bits_left = 8;
         }
 
-        debug_assert!(self.read_offset < self.movetext.len());
-
-        let byte = self.movetext[self.read_offset] << (8 - self.read_bits_left);
+        let byte = byte_at(movetext, self.read_offset)? << (8 - self.read_bits_left);
         let mut bits = byte >> (8 - count);
 
         if count > self.read_bits_left {
             let spill_count = count - self.read_bits_left;
 
-            bits |= self.movetext[self.read_offset + 1] >> (8 - spill_count);
+            bits |= byte_at(movetext, self.read_offset + 1)? >> (8 - spill_count);
             self.read_bits_left += 8;
             self.read_offset += 1;
         }
 
         self.read_bits_left -= count;
-        bits
+        Ok(bits)
     }
 
-    pub fn extract_vle16(&mut self, block_size: usize) -> u16 {
+    pub fn extract_vle16(&mut self, movetext: &[u8], block_size: usize) -> Result<u16> {
         let mask = (1 << block_size) - 1;
         let mut v = 0u16;
         let mut offset = 0;
 
         loop {
-            let block = self.extract_bits_le8(block_size + 1) as u16;
+            let block = self.extract_bits_le8(movetext, block_size + 1)? as u16;
             v |= (block & mask) << offset;
             if (block >> block_size) == 0 {
                 break;
             }
             offset += block_size;
+
+            if offset >= 16 {
+                return Err(CompressedReaderError::InvalidFormat(
+                    "Variable length value exceeds 16 bits".to_string(),
+                ));
+            }
         }
 
-        v
+        Ok(v)
     }
 
     pub fn num_read_bytes(&self) -> usize {
         self.read_offset + (self.read_bits_left != 8) as usize
     }
+
+    /// Number of bits read so far
+    pub fn bit_position(&self) -> u64 {
+        (self.read_offset * 8 + 8 - self.read_bits_left) as u64
+    }
+}
+
+impl Default for BitReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn byte_at(movetext: &[u8], offset: usize) -> Result<u8> {
+    movetext.get(offset).copied().ok_or_else(|| {
+        CompressedReaderError::InvalidFormat("Movetext overruns the chunk".to_string())
+    })
 }
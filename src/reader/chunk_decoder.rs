@@ -0,0 +1,116 @@
+use crate::training_data_entry::{PackedTrainingDataEntry, TrainingDataEntry};
+
+use super::{
+    move_score_list_reader::{check_position, PackedMoveScoreListReader},
+    training_data_reader::{CompressedReaderError, Result},
+};
+
+const STEM_SIZE: usize = std::mem::size_of::<PackedTrainingDataEntry>() + 2;
+
+/// Decodes the entries of a single chunk. Like the movelist reader it only
+/// keeps track of the read position, the chunk is passed to every call.
+#[derive(Debug, Clone, Default)]
+pub struct ChunkDecoder {
+    offset: usize,
+    movelist_reader: Option<PackedMoveScoreListReader>,
+}
+
+impl ChunkDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether another entry can be read from `chunk`, trailing bytes
+    /// too short to hold a stem are ignored
+    pub fn has_next(&self, chunk: &[u8]) -> bool {
+        self.movelist_reader.is_some() || self.offset + STEM_SIZE <= chunk.len()
+    }
+
+    /// Whether the next entry is the stem of a chain
+    pub fn is_chain_start(&self) -> bool {
+        self.movelist_reader.is_none()
+    }
+
+    pub fn next_entry(&mut self, chunk: &[u8]) -> Result<TrainingDataEntry> {
+        if let Some(ref mut reader) = self.movelist_reader {
+            let entry = reader.next_entry(&chunk[self.offset..])?;
+
+            if !reader.has_next() {
+                self.offset += reader.num_read_bytes();
+                self.movelist_reader = None;
+            }
+
+            return Ok(entry);
+        }
+
+        let stem = chunk
+            .get(self.offset..self.offset + STEM_SIZE)
+            .ok_or_else(|| {
+                CompressedReaderError::InvalidFormat(
+                    "Chunk is too small to hold an entry".to_string(),
+                )
+            })?;
+
+        let mut packed = PackedTrainingDataEntry::default();
+        packed.copy_from_slice(&stem[..STEM_SIZE - 2]);
+
+        let num_plies = u16::from_be_bytes([stem[STEM_SIZE - 2], stem[STEM_SIZE - 1]]);
+        self.offset += STEM_SIZE;
+
+        let entry = packed.unpack_entry();
+
+        check_position(&entry.pos)?;
+
+        if num_plies > 0 {
+            // the movetext starts at the current offset, which stays
+            // untouched until the whole movelist has been read
+            self.movelist_reader = Some(PackedMoveScoreListReader::new(entry, num_plies));
+        }
+
+        Ok(entry)
+    }
+
+    /// Get the offset of the current chain within the chunk, the number of
+    /// entries already read from it and the bit position in its movetext
+    pub fn position(&self) -> (usize, u32, u64) {
+        match self.movelist_reader {
+            Some(ref reader) => (
+                self.offset - STEM_SIZE,
+                reader.num_read_plies() as u32 + 1,
+                reader.bit_position(),
+            ),
+            None => (self.offset, 0, 0),
+        }
+    }
+
+    /// Continue at a position returned by [`Self::position`], the chain is
+    /// decoded again up to that point
+    pub fn resume(chunk: &[u8], offset: usize, num_read: u32, bit_position: u64) -> Result<Self> {
+        let mut decoder = Self {
+            offset,
+            movelist_reader: None,
+        };
+
+        for _ in 0..num_read {
+            if !decoder.has_next(chunk) {
+                break;
+            }
+
+            decoder.next_entry(chunk)?;
+        }
+
+        if decoder.position() != (offset, num_read, bit_position) {
+            return Err(CompressedReaderError::InvalidFormat(
+                "Position doesn't match the chunk".to_string(),
+            ));
+        }
+
+        Ok(decoder)
+    }
+
+    /// Drop the rest of `chunk`, used once it turned out to be corrupt
+    pub fn skip(&mut self, chunk: &[u8]) {
+        self.movelist_reader = None;
+        self.offset = chunk.len();
+    }
+}
@@ -0,0 +1,337 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::time::UNIX_EPOCH;
+
+use crate::training_data_file::CompressedTrainingDataFile;
+
+use super::{
+    chunk_decoder::ChunkDecoder,
+    training_data_reader::{CompressedReaderError, Result},
+};
+
+const INDEX_MAGIC: &[u8; 8] = b"BINPIDX2";
+
+/// Location and contents of a single chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkInfo {
+    /// Offset of the chunk header from the start of the binpack
+    pub offset: u64,
+    /// Size of the chunk data, without the header
+    pub size: u32,
+    /// Number of entries that can be read from the chunk
+    pub num_entries: u64,
+}
+
+/// An index over all chunks of a binpack, used to seek a reader to a chunk or entry.
+///
+/// Building the index decodes the whole file once, so it can be saved next to
+/// the binpack and loaded again with [`ChunkIndex::load_or_build`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChunkIndex {
+    chunks: Vec<ChunkInfo>,
+    // number of entries before each chunk
+    first_entries: Vec<u64>,
+    file_size: u64,
+    // modification time of the indexed binpack in nanoseconds, 0 if unknown
+    modified: u64,
+}
+
+impl ChunkIndex {
+    pub fn build(path: &str) -> Result<Self> {
+        let modified = modified_time(path)?;
+        let mut index = Self::from_reader(File::open(path)?)?;
+        index.modified = modified;
+        Ok(index)
+    }
+
+    /// Index a binpack read from any stream
+    pub fn from_reader<T: Read>(reader: T) -> Result<Self> {
+        let mut file = CompressedTrainingDataFile::from_stream(reader);
+        let mut chunks = Vec::new();
+
+        while file.has_next_chunk() {
+            let offset = file.read_bytes();
+            let chunk = file.read_next_chunk()?;
+
+            chunks.push(ChunkInfo {
+                offset,
+                size: chunk.len() as u32,
+                num_entries: count_entries(&chunk),
+            });
+        }
+
+        Ok(Self::from_chunks(chunks, file.read_bytes(), 0))
+    }
+
+    /// Load the sidecar index of the binpack at `path`, the index is built and
+    /// saved first if it is missing or doesn't match the size and modification
+    /// time of the binpack. Failing to save the index is not an error, so
+    /// binpacks on read-only storage can still be indexed.
+    pub fn load_or_build(path: &str) -> Result<Self> {
+        let file_size = std::fs::metadata(path)?.len();
+        let modified = modified_time(path)?;
+        let sidecar = Self::sidecar_path(path);
+
+        if let Ok(index) = Self::load(&sidecar) {
+            if index.file_size == file_size && index.modified == modified {
+                return Ok(index);
+            }
+        }
+
+        let index = Self::build(path)?;
+        let _ = index.save(&sidecar);
+        Ok(index)
+    }
+
+    /// Path of the sidecar index belonging to the binpack at `path`
+    pub fn sidecar_path(path: &str) -> String {
+        format!("{path}.idx")
+    }
+
+    pub fn load(path: &str) -> Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+
+        let mut magic = [0u8; 8];
+        file.read_exact(&mut magic)?;
+
+        if &magic != INDEX_MAGIC {
+            return Err(CompressedReaderError::InvalidFormat(
+                "Not a chunk index".to_string(),
+            ));
+        }
+
+        let file_size = read_u64(&mut file)?;
+        let modified = read_u64(&mut file)?;
+        let num_chunks = read_u64(&mut file)?;
+
+        let chunks = (0..num_chunks)
+            .map(|_| {
+                Ok(ChunkInfo {
+                    offset: read_u64(&mut file)?,
+                    size: read_u32(&mut file)?,
+                    num_entries: read_u64(&mut file)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self::from_chunks(chunks, file_size, modified))
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        file.write_all(INDEX_MAGIC)?;
+        file.write_all(&self.file_size.to_le_bytes())?;
+        file.write_all(&self.modified.to_le_bytes())?;
+        file.write_all(&(self.chunks.len() as u64).to_le_bytes())?;
+
+        for chunk in &self.chunks {
+            file.write_all(&chunk.offset.to_le_bytes())?;
+            file.write_all(&chunk.size.to_le_bytes())?;
+            file.write_all(&chunk.num_entries.to_le_bytes())?;
+        }
+
+        file.flush()?;
+        Ok(())
+    }
+
+    fn from_chunks(chunks: Vec<ChunkInfo>, file_size: u64, modified: u64) -> Self {
+        let first_entries = chunks
+            .iter()
+            .scan(0, |total, chunk| {
+                let first = *total;
+                *total += chunk.num_entries;
+                Some(first)
+            })
+            .collect();
+
+        Self {
+            chunks,
+            first_entries,
+            file_size,
+            modified,
+        }
+    }
+
+    pub fn chunks(&self) -> &[ChunkInfo] {
+        &self.chunks
+    }
+
+    /// Get the size of the indexed binpack in bytes
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    pub fn num_entries(&self) -> u64 {
+        self.first_entries.last().copied().unwrap_or(0)
+            + self.chunks.last().map_or(0, |chunk| chunk.num_entries)
+    }
+
+    /// Number of entries stored before the chunk at `index`
+    pub fn first_entry(&self, index: usize) -> Option<u64> {
+        self.first_entries.get(index).copied()
+    }
+
+    /// Find the chunk holding the entry with the given number, returns the
+    /// index of the chunk and the position of the entry within it
+    pub fn find_entry(&self, entry: u64) -> Option<(usize, u64)> {
+        if entry >= self.num_entries() {
+            return None;
+        }
+
+        let index = self.first_entries.partition_point(|&first| first <= entry) - 1;
+        Some((index, entry - self.first_entries[index]))
+    }
+}
+
+/// Modification time of the file at `path` in nanoseconds since the epoch,
+/// 0 if the platform doesn't provide it
+fn modified_time(path: &str) -> Result<u64> {
+    let modified = std::fs::metadata(path)?
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_nanos() as u64);
+
+    Ok(modified)
+}
+
+/// Count the entries a reader yields for `chunk`, a corrupt chunk ends early
+fn count_entries(chunk: &[u8]) -> u64 {
+    let mut decoder = ChunkDecoder::new();
+    let mut count = 0;
+
+    while decoder.has_next(chunk) && decoder.next_entry(chunk).is_ok() {
+        count += 1;
+    }
+
+    count
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    fn write_copies(num_copies: usize) -> NamedTempFile {
+        let data = std::fs::read("./test/ep1.binpack").unwrap();
+
+        let mut file = NamedTempFile::new().unwrap();
+        for _ in 0..num_copies {
+            file.write_all(&data).unwrap();
+        }
+        file.flush().unwrap();
+        file
+    }
+
+    #[test]
+    fn test_build_index() {
+        let file = write_copies(3);
+        let index = ChunkIndex::build(file.path().to_str().unwrap()).unwrap();
+
+        let chunk_entries = index.chunks()[0].num_entries;
+        assert!(chunk_entries > 1);
+
+        assert_eq!(index.chunks().len(), 3);
+        assert_eq!(index.chunks()[1].offset, 46);
+        assert_eq!(index.chunks()[1].size, 38);
+        assert_eq!(index.file_size(), 3 * 46);
+        assert_eq!(index.num_entries(), 3 * chunk_entries);
+        assert_eq!(index.first_entry(2), Some(2 * chunk_entries));
+
+        assert_eq!(index.find_entry(0), Some((0, 0)));
+        assert_eq!(index.find_entry(chunk_entries), Some((1, 0)));
+        assert_eq!(
+            index.find_entry(3 * chunk_entries - 1),
+            Some((2, chunk_entries - 1))
+        );
+        assert_eq!(index.find_entry(3 * chunk_entries), None);
+    }
+
+    #[test]
+    fn test_sidecar_roundtrip() {
+        let file = write_copies(2);
+        let path = file.path().to_str().unwrap();
+        let sidecar = ChunkIndex::sidecar_path(path);
+
+        let index = ChunkIndex::load_or_build(path).unwrap();
+        assert_eq!(ChunkIndex::load(&sidecar).unwrap(), index);
+        assert_eq!(ChunkIndex::load_or_build(path).unwrap(), index);
+
+        std::fs::remove_file(sidecar).unwrap();
+    }
+
+    #[test]
+    fn test_stale_sidecar_is_rebuilt() {
+        let file = write_copies(2);
+        let path = file.path().to_str().unwrap();
+        let sidecar = ChunkIndex::sidecar_path(path);
+
+        ChunkIndex::load_or_build(path).unwrap();
+
+        let more = write_copies(3);
+        std::fs::copy(more.path(), path).unwrap();
+
+        let index = ChunkIndex::load_or_build(path).unwrap();
+        assert_eq!(index.chunks().len(), 3);
+
+        std::fs::remove_file(sidecar).unwrap();
+    }
+
+    #[test]
+    fn test_modified_sidecar_is_rebuilt() {
+        let file = write_copies(2);
+        let path = file.path().to_str().unwrap();
+        let sidecar = ChunkIndex::sidecar_path(path);
+
+        ChunkIndex::load_or_build(path).unwrap();
+
+        // same size but different contents, only the modification time changes
+        let mut stale = ChunkIndex::load(&sidecar).unwrap();
+        stale.chunks.pop();
+        stale.modified -= 1;
+        stale.save(&sidecar).unwrap();
+
+        let index = ChunkIndex::load_or_build(path).unwrap();
+        assert_eq!(index.chunks().len(), 2);
+
+        std::fs::remove_file(sidecar).unwrap();
+    }
+
+    #[test]
+    fn test_unwritable_sidecar() {
+        let file = write_copies(1);
+        let path = file.path().to_str().unwrap();
+        let sidecar = ChunkIndex::sidecar_path(path);
+
+        // a directory in place of the sidecar can't be written
+        std::fs::create_dir(&sidecar).unwrap();
+
+        let index = ChunkIndex::load_or_build(path).unwrap();
+        assert_eq!(index.chunks().len(), 1);
+
+        std::fs::remove_dir(sidecar).unwrap();
+    }
+
+    #[test]
+    fn test_load_invalid_index() {
+        let file = write_copies(1);
+
+        assert!(matches!(
+            ChunkIndex::load(file.path().to_str().unwrap()),
+            Err(CompressedReaderError::InvalidFormat(_))
+        ));
+    }
+}
@@ -0,0 +1,342 @@
+use std::iter::FusedIterator;
+
+use crate::{rng::Rng, training_data_entry::TrainingDataEntry};
+
+use super::training_data_reader::{
+    CompressedReaderError, CompressedTrainingDataEntryReader, Result,
+};
+
+#[derive(Debug, Clone)]
+pub struct DatasetOptions {
+    /// How often every source is read, `None` cycles the sources forever
+    pub num_epochs: Option<u64>,
+    pub seed: u64,
+}
+
+impl Default for DatasetOptions {
+    fn default() -> Self {
+        Self {
+            num_epochs: Some(1),
+            seed: 0,
+        }
+    }
+}
+
+/// The files matching one pattern, read one after another
+#[derive(Debug)]
+struct Source {
+    weight: f64,
+    files: Vec<(String, u64)>,
+    file_index: usize,
+    reader: Option<CompressedTrainingDataEntryReader>,
+    epoch: u64,
+    // size of the files already read in this epoch
+    done_bytes: u64,
+    entries_in_epoch: u64,
+    is_end: bool,
+}
+
+impl Source {
+    fn new(pattern: &str, weight: f64) -> Result<Self> {
+        if !(weight.is_finite() && weight > 0.0) {
+            return Err(CompressedReaderError::InvalidFormat(format!(
+                "Invalid weight {weight} for {pattern}"
+            )));
+        }
+
+        let paths = glob::glob(pattern).map_err(|e| {
+            CompressedReaderError::InvalidFormat(format!("Invalid pattern {pattern}: {e}"))
+        })?;
+
+        let mut files = Vec::new();
+
+        for path in paths {
+            let path = path.map_err(std::io::Error::from)?;
+            let size = std::fs::metadata(&path)?.len();
+            files.push((path.to_string_lossy().into_owned(), size));
+        }
+
+        if files.is_empty() {
+            return Err(CompressedReaderError::InvalidFormat(format!(
+                "No files match {pattern}"
+            )));
+        }
+
+        Ok(Self {
+            weight,
+            files,
+            file_index: 0,
+            reader: None,
+            epoch: 0,
+            done_bytes: 0,
+            entries_in_epoch: 0,
+            is_end: false,
+        })
+    }
+
+    fn next_entry(&mut self, num_epochs: Option<u64>) -> Option<Result<TrainingDataEntry>> {
+        loop {
+            if let Some(reader) = &mut self.reader {
+                if reader.has_next() {
+                    // errors don't count, a source of corrupt chunks would never end otherwise
+                    let entry = reader.next_entry();
+                    self.entries_in_epoch += entry.is_ok() as u64;
+                    return Some(entry);
+                }
+
+                self.reader = None;
+                self.done_bytes += self.files[self.file_index].1;
+                self.file_index += 1;
+            }
+
+            if self.file_index == self.files.len() {
+                self.epoch += 1;
+                self.file_index = 0;
+                self.done_bytes = 0;
+
+                let no_entries = std::mem::take(&mut self.entries_in_epoch) == 0;
+
+                if no_entries || num_epochs.is_some_and(|n| self.epoch >= n) {
+                    self.is_end = true;
+                    return None;
+                }
+            }
+
+            match CompressedTrainingDataEntryReader::new(&self.files[self.file_index].0) {
+                Ok(reader) => self.reader = Some(reader),
+                Err(e) => {
+                    self.done_bytes += self.files[self.file_index].1;
+                    self.file_index += 1;
+
+                    if !matches!(e, CompressedReaderError::EndOfFile) {
+                        return Some(Err(e));
+                    }
+                }
+            }
+        }
+    }
+
+    fn read_bytes(&self) -> u64 {
+        self.done_bytes + self.reader.as_ref().map_or(0, |r| r.read_bytes())
+    }
+
+    fn file_size(&self) -> u64 {
+        self.files.iter().map(|(_, size)| size).sum()
+    }
+}
+
+/// Mixes the entries of several sources of binpacks, every source is a path
+/// or glob pattern with a weight. The next entry is taken from a random
+/// source with a probability proportional to its weight, so small sources
+/// are cycled more often when reading multiple epochs.
+///
+/// Once a source has been read `num_epochs` times it is dropped and the
+/// remaining sources are mixed according to their weights.
+#[derive(Debug)]
+pub struct Dataset {
+    sources: Vec<Source>,
+    num_epochs: Option<u64>,
+    rng: Rng,
+}
+
+impl Dataset {
+    pub fn new(sources: &[(&str, f64)], options: DatasetOptions) -> Result<Self> {
+        let sources = sources
+            .iter()
+            .map(|&(pattern, weight)| Source::new(pattern, weight))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            sources,
+            num_epochs: options.num_epochs,
+            rng: Rng::new(options.seed),
+        })
+    }
+
+    /// Read the next entry together with the index of its source, returns
+    /// [`CompressedReaderError::EndOfFile`] once all sources are exhausted.
+    pub fn next_entry_with_source(&mut self) -> Result<(usize, TrainingDataEntry)> {
+        loop {
+            let source = self.pick_source().ok_or(CompressedReaderError::EndOfFile)?;
+
+            if let Some(entry) = self.sources[source].next_entry(self.num_epochs) {
+                return entry.map(|entry| (source, entry));
+            }
+        }
+    }
+
+    pub fn next_entry(&mut self) -> Result<TrainingDataEntry> {
+        self.next_entry_with_source().map(|(_, entry)| entry)
+    }
+
+    fn pick_source(&mut self) -> Option<usize> {
+        let active = || self.sources.iter().enumerate().filter(|(_, s)| !s.is_end);
+
+        let total = active().map(|(_, s)| s.weight).sum::<f64>();
+        let mut target = self.rng.next_f64() * total;
+        let mut last = None;
+
+        for (index, source) in active() {
+            if target < source.weight {
+                return Some(index);
+            }
+
+            target -= source.weight;
+            last = Some(index);
+        }
+
+        // rounding errors can leave a tiny remainder
+        last
+    }
+
+    pub fn num_sources(&self) -> usize {
+        self.sources.len()
+    }
+
+    /// Number of completed epochs of a source
+    pub fn epoch(&self, source: usize) -> u64 {
+        self.sources[source].epoch
+    }
+
+    /// Get how much of a source has been read in the current epoch
+    pub fn read_bytes(&self, source: usize) -> u64 {
+        self.sources[source].read_bytes()
+    }
+
+    /// Get the size of all files of a source in bytes
+    pub fn file_size(&self, source: usize) -> u64 {
+        self.sources[source].file_size()
+    }
+}
+
+impl Iterator for Dataset {
+    type Item = Result<TrainingDataEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_entry() {
+            Err(CompressedReaderError::EndOfFile) => None,
+            entry => Some(entry),
+        }
+    }
+}
+
+impl FusedIterator for Dataset {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn write_copies(dir: &std::path::Path, name: &str, num_copies: usize) -> String {
+        let data = std::fs::read("./test/ep1.binpack").unwrap();
+        let path = dir.join(name);
+
+        let mut file = std::fs::File::create(&path).unwrap();
+        for _ in 0..num_copies {
+            file.write_all(&data).unwrap();
+        }
+
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_single_epoch_reads_everything() {
+        let dir = tempfile::tempdir().unwrap();
+        write_copies(dir.path(), "a1.binpack", 2);
+        write_copies(dir.path(), "a2.binpack", 3);
+        let b = write_copies(dir.path(), "b.binpack", 1);
+
+        let pattern = dir.path().join("a*.binpack");
+        let sources = [(pattern.to_str().unwrap(), 1.0), (b.as_str(), 2.0)];
+
+        let mut dataset = Dataset::new(&sources, DatasetOptions::default()).unwrap();
+        assert_eq!(dataset.file_size(0), 5 * 46);
+        assert_eq!(dataset.file_size(1), 46);
+
+        let mut counts = [0; 2];
+        while let Ok((source, _)) = dataset.next_entry_with_source() {
+            counts[source] += 1;
+        }
+
+        assert_eq!(counts, [15, 3]);
+        assert_eq!(dataset.epoch(0), 1);
+        assert_eq!(dataset.epoch(1), 1);
+        assert_eq!(dataset.read_bytes(0), 0);
+    }
+
+    #[test]
+    fn test_weighted_mixing() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = write_copies(dir.path(), "a.binpack", 10);
+        let b = write_copies(dir.path(), "b.binpack", 1);
+
+        let options = DatasetOptions {
+            num_epochs: None,
+            seed: 7,
+        };
+        let mut dataset = Dataset::new(&[(&a, 3.0), (&b, 1.0)], options).unwrap();
+
+        let mut counts = [0; 2];
+        for _ in 0..4000 {
+            let (source, _) = dataset.next_entry_with_source().unwrap();
+            counts[source] += 1;
+        }
+
+        assert!((2800..3200).contains(&counts[0]), "{counts:?}");
+        assert!(dataset.epoch(1) > dataset.epoch(0));
+        assert!(dataset.read_bytes(0) <= dataset.file_size(0));
+    }
+
+    #[test]
+    fn test_multiple_epochs() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = write_copies(dir.path(), "a.binpack", 2);
+        let empty = write_copies(dir.path(), "empty.binpack", 0);
+
+        let options = DatasetOptions {
+            num_epochs: Some(3),
+            seed: 0,
+        };
+        let dataset = Dataset::new(&[(&a, 1.0), (&empty, 1.0)], options).unwrap();
+
+        let entries = dataset.map(|e| e.unwrap()).collect::<Vec<_>>();
+        assert_eq!(entries.len(), 3 * 6);
+        assert_eq!(entries[..6], entries[6..12]);
+    }
+
+    #[test]
+    fn test_corrupt_source_ends() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("corrupt.binpack");
+
+        let mut data = b"BINP".to_vec();
+        data.extend_from_slice(&40u32.to_le_bytes());
+        data.extend_from_slice(&[0xFF; 40]);
+        std::fs::write(&path, data).unwrap();
+
+        let options = DatasetOptions {
+            num_epochs: None,
+            seed: 0,
+        };
+        let dataset = Dataset::new(&[(path.to_str().unwrap(), 1.0)], options).unwrap();
+
+        let entries = dataset.take(10).collect::<Vec<_>>();
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].is_err());
+    }
+
+    #[test]
+    fn test_invalid_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = write_copies(dir.path(), "a.binpack", 1);
+        let missing = dir.path().join("missing*.binpack");
+
+        assert!(Dataset::new(&[(&a, 0.0)], DatasetOptions::default()).is_err());
+        assert!(Dataset::new(
+            &[(missing.to_str().unwrap(), 1.0)],
+            DatasetOptions::default()
+        )
+        .is_err());
+        assert!(Dataset::new(&[("[", 1.0)], DatasetOptions::default()).is_err());
+    }
+}
@@ -0,0 +1,308 @@
+use std::fs::File;
+use std::iter::FusedIterator;
+use std::ops::Range;
+
+use memmap2::Mmap;
+
+use crate::{
+    binpack_error::BinpackError,
+    rng::Rng,
+    training_data_entry::TrainingDataEntry,
+    training_data_file::{parse_chunk_header, HEADER_SIZE},
+};
+
+use super::{
+    chunk_decoder::ChunkDecoder,
+    skip_filter::SkipFilter,
+    training_data_reader::{CompressedReaderError, Result},
+};
+
+/// A binpack mapped into memory. The chunk boundaries are indexed once when
+/// the file is opened, afterwards entries are decoded straight from the
+/// mapped pages without copying the chunks.
+///
+/// `MmapBinpack` is `Sync`, so several threads can read from one mapping,
+/// e.g. each with its own [`MmapBinpack::reader_for_chunks`].
+#[derive(Debug)]
+pub struct MmapBinpack {
+    mmap: Mmap,
+    chunks: Vec<Range<usize>>,
+}
+
+impl MmapBinpack {
+    pub fn open(path: &str) -> Result<Self> {
+        let file = File::open(path)?;
+
+        // SAFETY: the mapping is read-only, the file must not be
+        // truncated or modified by someone else while it is mapped
+        let mmap = unsafe { Mmap::map(&file)? };
+        let chunks = index_chunks(&mmap)?;
+
+        Ok(Self { mmap, chunks })
+    }
+
+    /// Get the size of the file in bytes
+    pub fn file_size(&self) -> u64 {
+        self.mmap.len() as u64
+    }
+
+    pub fn num_chunks(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Get the data of a chunk without its header
+    pub fn chunk(&self, index: usize) -> Option<&[u8]> {
+        self.chunks
+            .get(index)
+            .map(|range| &self.mmap[range.clone()])
+    }
+
+    /// Read all entries of the file
+    pub fn reader(&self) -> MmapTrainingDataEntryReader<'_> {
+        self.reader_for_chunks(0..self.num_chunks())
+    }
+
+    /// Read the entries of the given chunks only, the range is clamped to the
+    /// chunks of the file
+    pub fn reader_for_chunks(&self, chunks: Range<usize>) -> MmapTrainingDataEntryReader<'_> {
+        let end = chunks.end.min(self.num_chunks());
+        let start = chunks.start.min(end);
+
+        let mut reader = MmapTrainingDataEntryReader {
+            binpack: self,
+            chunks: start..end,
+            decoder: ChunkDecoder::new(),
+            filter: None,
+            filter_rng: Rng::new(0),
+        };
+
+        reader.skip_exhausted_chunks();
+        reader
+    }
+}
+
+/// Find the data ranges of all chunks, the whole file is validated up front
+fn index_chunks(data: &[u8]) -> Result<Vec<Range<usize>>> {
+    let mut chunks = Vec::new();
+    let mut offset = 0;
+
+    while offset < data.len() {
+        let header: &[u8; HEADER_SIZE] = data
+            .get(offset..offset + HEADER_SIZE)
+            .and_then(|header| header.try_into().ok())
+            .ok_or(BinpackError::InvalidMagic)?;
+
+        let start = offset + HEADER_SIZE;
+        let end = start + parse_chunk_header(header)? as usize;
+
+        if end > data.len() {
+            return Err(CompressedReaderError::BinpackError(
+                BinpackError::InvalidFormat("Chunk is truncated. Malformed file?".to_string()),
+            ));
+        }
+
+        chunks.push(start..end);
+        offset = end;
+    }
+
+    Ok(chunks)
+}
+
+/// Reads entries from a range of chunks of a [`MmapBinpack`].
+#[derive(Debug, Clone)]
+pub struct MmapTrainingDataEntryReader<'a> {
+    binpack: &'a MmapBinpack,
+    // the chunks left to read, the first one is the current chunk
+    chunks: Range<usize>,
+    decoder: ChunkDecoder,
+    filter: Option<SkipFilter>,
+    filter_rng: Rng,
+}
+
+impl<'a> MmapTrainingDataEntryReader<'a> {
+    pub fn has_next(&self) -> bool {
+        !self.chunks.is_empty()
+    }
+
+    /// Skip all entries matching `filter` from now on, see
+    /// [`super::training_data_reader::CompressedTrainingDataEntryReader::set_filter`]
+    pub fn set_filter(&mut self, filter: SkipFilter) {
+        self.filter_rng = Rng::new(filter.seed);
+        self.filter = Some(filter);
+    }
+
+    /// Read the next entry, returns [`CompressedReaderError::EndOfFile`]
+    /// if there are no entries left.
+    ///
+    /// If the current chunk turns out to be corrupt the error is returned and
+    /// the rest of the chunk is skipped, so reading can continue with the next chunk.
+    pub fn next_entry(&mut self) -> Result<TrainingDataEntry> {
+        loop {
+            let entry = self.next_unfiltered_entry()?;
+
+            match self.filter {
+                Some(ref filter) if filter.skips(&entry, &mut self.filter_rng) => continue,
+                _ => return Ok(entry),
+            }
+        }
+    }
+
+    fn next_unfiltered_entry(&mut self) -> Result<TrainingDataEntry> {
+        if !self.has_next() {
+            return Err(CompressedReaderError::EndOfFile);
+        }
+
+        let chunk = self.current_chunk();
+        let entry = self
+            .decoder
+            .next_entry(chunk)
+            .inspect_err(|_| self.decoder.skip(chunk));
+
+        self.skip_exhausted_chunks();
+        entry
+    }
+
+    fn current_chunk(&self) -> &'a [u8] {
+        let binpack = self.binpack;
+        &binpack.mmap[binpack.chunks[self.chunks.start].clone()]
+    }
+
+    fn skip_exhausted_chunks(&mut self) {
+        while self.has_next() && !self.decoder.has_next(self.current_chunk()) {
+            self.chunks.start += 1;
+            self.decoder = ChunkDecoder::new();
+        }
+    }
+}
+
+impl Iterator for MmapTrainingDataEntryReader<'_> {
+    type Item = Result<TrainingDataEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_entry() {
+            Err(CompressedReaderError::EndOfFile) => None,
+            entry => Some(entry),
+        }
+    }
+}
+
+impl FusedIterator for MmapTrainingDataEntryReader<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::training_data_reader::CompressedTrainingDataEntryReader;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn read_all(path: &str) -> Vec<TrainingDataEntry> {
+        let reader = CompressedTrainingDataEntryReader::new(path).unwrap();
+        reader.map(|e| e.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_mmap_reader_matches_stream_reader() {
+        let binpack = MmapBinpack::open("./test/ep1.binpack").unwrap();
+        let entries = binpack.reader().map(|e| e.unwrap()).collect::<Vec<_>>();
+
+        assert_eq!(binpack.file_size(), 46);
+        assert_eq!(entries, read_all("./test/ep1.binpack"));
+    }
+
+    #[test]
+    fn test_mmap_shared_between_threads() {
+        let data = std::fs::read("./test/ep1.binpack").unwrap();
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&data).unwrap();
+        file.write_all(&data).unwrap();
+        file.flush().unwrap();
+        let path = file.path().to_str().unwrap();
+
+        let binpack = MmapBinpack::open(path).unwrap();
+        assert_eq!(binpack.num_chunks(), 2);
+
+        let entries = std::thread::scope(|s| {
+            let handles = (0..binpack.num_chunks())
+                .map(|i| {
+                    let binpack = &binpack;
+                    s.spawn(move || {
+                        binpack
+                            .reader_for_chunks(i..i + 1)
+                            .map(|e| e.unwrap())
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        assert_eq!(entries, read_all(path));
+    }
+
+    #[test]
+    fn test_mmap_filter() {
+        let binpack = MmapBinpack::open("./test/ep1.binpack").unwrap();
+        let entries = read_all("./test/ep1.binpack");
+        let last_ply = entries.last().unwrap().ply;
+
+        let mut reader = binpack.reader();
+        reader.set_filter(SkipFilter {
+            min_ply: last_ply,
+            ..Default::default()
+        });
+
+        let filtered = reader.map(|e| e.unwrap()).collect::<Vec<_>>();
+        let expected = entries
+            .into_iter()
+            .filter(|e| e.ply >= last_ply)
+            .collect::<Vec<_>>();
+        assert_eq!(filtered, expected);
+
+        let mut reader = binpack.reader();
+        reader.set_filter(SkipFilter {
+            min_ply: last_ply + 1,
+            ..Default::default()
+        });
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_mmap_truncated_file() {
+        let data = std::fs::read("./test/ep1.binpack").unwrap();
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&data[..data.len() - 1]).unwrap();
+        file.flush().unwrap();
+
+        assert!(matches!(
+            MmapBinpack::open(file.path().to_str().unwrap()),
+            Err(CompressedReaderError::BinpackError(
+                BinpackError::InvalidFormat(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_mmap_skip_corrupt_chunk() {
+        let data = std::fs::read("./test/ep1.binpack").unwrap();
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"BINP").unwrap();
+        file.write_all(&40u32.to_le_bytes()).unwrap();
+        file.write_all(&[0xFF; 40]).unwrap();
+        file.write_all(&data).unwrap();
+        file.flush().unwrap();
+
+        let binpack = MmapBinpack::open(file.path().to_str().unwrap()).unwrap();
+        let mut reader = binpack.reader();
+
+        assert!(reader.next().unwrap().is_err());
+        let entries = reader.map(|e| e.unwrap()).collect::<Vec<_>>();
+        assert_eq!(entries, read_all("./test/ep1.binpack"));
+    }
+}
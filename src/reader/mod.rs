@@ -1,3 +1,11 @@
 mod bitreader;
+mod chunk_decoder;
+pub mod chunk_index;
+pub mod dataset;
+pub mod mmap_reader;
 mod move_score_list_reader;
+pub mod parallel_reader;
+pub mod reader_cursor;
+pub mod shuffle_reader;
+pub mod skip_filter;
 pub mod training_data_reader;
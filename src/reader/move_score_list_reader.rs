@@ -8,26 +8,32 @@ use crate::{
         coords::{FlatSquareOffset, Rank, Square},
         piece::Piece,
         piecetype::PieceType,
-        r#move::Move,
+        position::Position,
+        r#move::{Move, MoveType},
     },
     training_data_entry::TrainingDataEntry,
 };
 
-use super::bitreader::BitReader;
+use super::{
+    bitreader::BitReader,
+    training_data_reader::{CompressedReaderError, Result},
+};
 
-#[derive(Debug)]
-pub struct PackedMoveScoreListReader<'a> {
-    reader: BitReader<'a>,
+/// Decodes the movetext following a stem, the movetext is borrowed
+/// from the chunk for every call instead of being stored.
+#[derive(Debug, Clone)]
+pub struct PackedMoveScoreListReader {
+    reader: BitReader,
     last_score: i16,
     num_plies: u16,
     num_read_plies: u16,
     entry: TrainingDataEntry,
 }
 
-impl<'a> PackedMoveScoreListReader<'a> {
-    pub fn new(entry: TrainingDataEntry, movetext: &'a [u8], num_plies: u16) -> Self {
+impl PackedMoveScoreListReader {
+    pub fn new(entry: TrainingDataEntry, num_plies: u16) -> Self {
         Self {
-            reader: BitReader::new(movetext),
+            reader: BitReader::new(),
             num_plies,
             entry,
             num_read_plies: 0,
@@ -39,17 +45,19 @@ impl<'a> PackedMoveScoreListReader<'a> {
         self.num_read_plies < self.num_plies
     }
 
-    pub fn next_entry(&mut self) -> TrainingDataEntry {
+    pub fn next_entry(&mut self, movetext: &[u8]) -> Result<TrainingDataEntry> {
+        check_move(&self.entry.pos, self.entry.mv)?;
+
         self.entry.pos.do_move(self.entry.mv);
-        let (mv, score) = self.next_move_score();
+        let (mv, score) = self.next_move_score(movetext)?;
         self.entry.mv = mv;
         self.entry.score = score;
-        self.entry.ply += 1;
+        self.entry.ply = self.entry.ply.wrapping_add(1);
         self.entry.result = -self.entry.result;
-        self.entry
+        Ok(self.entry)
     }
 
-    pub fn next_move_score(&mut self) -> (Move, i16) {
+    pub fn next_move_score(&mut self, movetext: &[u8]) -> Result<(Move, i16)> {
         const SCORE_VLE_BLOCK_SIZE: usize = 4;
 
         // if !self.has_next() {
@@ -65,21 +73,25 @@ impl<'a> PackedMoveScoreListReader<'a> {
 
         let piece_id = self
             .reader
-            .extract_bits_le8(used_bits_safe(our_pieces.count() as u64));
+            .extract_bits_le8(movetext, used_bits_safe(our_pieces.count() as u64))?;
+
+        if piece_id as u32 >= our_pieces.count() {
+            return Err(invalid_movetext("piece index out of range"));
+        }
 
-        let move_ = self.decode_move(piece_id, occupied);
+        let move_ = self.decode_move(movetext, piece_id, occupied)?;
 
-        let delta = unsigned_to_signed(self.reader.extract_vle16(SCORE_VLE_BLOCK_SIZE));
+        let delta = unsigned_to_signed(self.reader.extract_vle16(movetext, SCORE_VLE_BLOCK_SIZE)?);
 
         let score = self.last_score.wrapping_add(delta);
         self.last_score = -score;
 
         self.num_read_plies += 1;
 
-        (move_, score)
+        Ok((move_, score))
     }
 
-    fn decode_move(&mut self, piece_id: u8, occupied: Bitboard) -> Move {
+    fn decode_move(&mut self, movetext: &[u8], piece_id: u8, occupied: Bitboard) -> Result<Move> {
         let pos = &self.entry.pos;
 
         let side_to_move = pos.side_to_move();
@@ -134,29 +146,38 @@ impl<'a> PackedMoveScoreListReader<'a> {
                 let destinations_count = destinations.count();
 
                 if from.rank() == promotion_rank {
-                    let move_id = self
-                        .reader
-                        .extract_bits_le8(used_bits_safe((destinations_count * 4) as u64));
-                    let pt =
-                        PieceType::from_ordinal(PieceType::Knight.ordinal() + (move_id % 4) as u8);
+                    let move_id = self.reader.extract_bits_le8(
+                        movetext,
+                        used_bits_safe((destinations_count * 4) as u64),
+                    )?;
+
+                    if move_id as u32 >= destinations_count * 4 {
+                        return Err(invalid_movetext("move index out of range"));
+                    }
+
+                    let pt = PieceType::from_ordinal(PieceType::Knight.ordinal() + (move_id % 4));
                     let promoted_piece = Piece::new(pt, side_to_move);
                     let to =
                         Square::new(nth_set_bit_index(destinations.bits(), move_id as u64 / 4));
 
-                    Move::promotion(from, to, promoted_piece)
+                    Ok(Move::promotion(from, to, promoted_piece))
                 } else {
                     let move_id = self
                         .reader
-                        .extract_bits_le8(used_bits_safe(destinations_count as u64));
+                        .extract_bits_le8(movetext, used_bits_safe(destinations_count as u64))?;
+
+                    if move_id as u32 >= destinations_count {
+                        return Err(invalid_movetext("move index out of range"));
+                    }
 
                     let idx = nth_set_bit_index(destinations.bits(), move_id as u64);
 
                     let to = Square::new(idx);
 
                     if to == ep_square {
-                        Move::en_passant(from, to)
+                        Ok(Move::en_passant(from, to))
                     } else {
-                        Move::normal(from, to)
+                        Ok(Move::normal(from, to))
                     }
                 }
             }
@@ -177,7 +198,14 @@ impl<'a> PackedMoveScoreListReader<'a> {
                     (castling_rights & our_castling_rights_mask).count_ones() as usize;
 
                 let offset = attacks_size as usize + num_castlings;
-                let move_id = self.reader.extract_bits_le8(used_bits_safe(offset as u64)) as u32;
+                let move_id = self
+                    .reader
+                    .extract_bits_le8(movetext, used_bits_safe(offset as u64))?
+                    as u32;
+
+                if move_id as usize >= offset {
+                    return Err(invalid_movetext("move index out of range"));
+                }
 
                 if move_id >= attacks_size {
                     let idx = move_id - attacks_size;
@@ -192,10 +220,10 @@ impl<'a> PackedMoveScoreListReader<'a> {
                         CastleType::Short
                     };
 
-                    Move::from_castle(castle_type, side_to_move)
+                    Ok(Move::from_castle(castle_type, side_to_move))
                 } else {
                     let to = Square::new(nth_set_bit_index(attacks.bits(), move_id as u64));
-                    Move::normal(from, to)
+                    Ok(Move::normal(from, to))
                 }
             }
 
@@ -204,10 +232,15 @@ impl<'a> PackedMoveScoreListReader<'a> {
                 let attacks = Attacks::piece_attacks(piece_type, from, occupied) & !our_pieces;
                 let move_id = self
                     .reader
-                    .extract_bits_le8(used_bits_safe(attacks.count() as u64));
+                    .extract_bits_le8(movetext, used_bits_safe(attacks.count() as u64))?;
+
+                if move_id as u32 >= attacks.count() {
+                    return Err(invalid_movetext("move index out of range"));
+                }
+
                 let idx = nth_set_bit_index(attacks.bits(), move_id as u64);
                 let to = Square::new(idx);
-                Move::normal(from, to)
+                Ok(Move::normal(from, to))
             }
         }
     }
@@ -215,6 +248,153 @@ impl<'a> PackedMoveScoreListReader<'a> {
     pub fn num_read_bytes(&self) -> usize {
         self.reader.num_read_bytes()
     }
+
+    pub fn num_read_plies(&self) -> u16 {
+        self.num_read_plies
+    }
+
+    pub fn bit_position(&self) -> u64 {
+        self.reader.bit_position()
+    }
 }
 
 // Helper functions
+
+fn invalid_movetext(reason: &str) -> CompressedReaderError {
+    CompressedReaderError::InvalidFormat(format!("Invalid movetext: {}", reason))
+}
+
+/// Make sure the position decoded from a stem is consistent enough to decode moves on it.
+pub(super) fn check_position(pos: &Position) -> Result<()> {
+    let kings = pos.pieces_bb_color(Color::White, PieceType::King).count() == 1
+        && pos.pieces_bb_color(Color::Black, PieceType::King).count() == 1;
+
+    let pawns = pos.pieces_bb_color(Color::White, PieceType::Pawn)
+        | pos.pieces_bb_color(Color::Black, PieceType::Pawn);
+    let back_ranks = Bitboard::from_rank(0) | Bitboard::from_rank(7);
+
+    // the square behind the pawn that was just pushed has to be empty
+    // and the pushed pawn has to be in front of it
+    let ep_square = pos.ep_square();
+    let (ep_rank, forward) = if pos.side_to_move() == Color::White {
+        (Rank::SIXTH, FlatSquareOffset::new(0, -1))
+    } else {
+        (Rank::THIRD, FlatSquareOffset::new(0, 1))
+    };
+    let ep = ep_square == Square::NONE
+        || (ep_square.index() < 64
+            && ep_square.rank() == ep_rank
+            && pos.piece_at(ep_square) == Piece::none()
+            && pos.piece_at(ep_square + forward.neg()) == Piece::none()
+            && pos.piece_at(ep_square + forward)
+                == Piece::new(PieceType::Pawn, !pos.side_to_move()));
+
+    if !kings || (pawns & back_ranks).count() > 0 || !ep {
+        return Err(CompressedReaderError::InvalidFormat(format!(
+            "Invalid position {}",
+            pos.fen()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Make sure `mv` can be played on `pos` without corrupting it,
+/// this doesn't check whether the move is legal.
+pub(super) fn check_move(pos: &Position, mv: Move) -> Result<()> {
+    let invalid =
+        || CompressedReaderError::InvalidFormat(format!("Invalid move {}{}", mv.from(), mv.to()));
+
+    if mv.from() == Square::NONE || mv.to() == Square::NONE || mv.from() == mv.to() {
+        return Err(invalid());
+    }
+
+    let stm = pos.side_to_move();
+    let piece = pos.piece_at(mv.from());
+    let target = pos.piece_at(mv.to());
+
+    if piece == Piece::none() || piece.color() != stm {
+        return Err(invalid());
+    }
+
+    let is_pawn = piece.piece_type() == PieceType::Pawn;
+
+    match mv.mtype() {
+        MoveType::Castle => {
+            let (king_sq, rook_squares) = if stm == Color::White {
+                (Square::E1, [Square::A1, Square::H1])
+            } else {
+                (Square::E8, [Square::A8, Square::H8])
+            };
+
+            let low = mv.from().index().min(mv.to().index());
+            let high = mv.from().index().max(mv.to().index());
+            let between = Bitboard::from_before(high) & !Bitboard::from_before(low + 1);
+
+            if piece.piece_type() != PieceType::King
+                || mv.from() != king_sq
+                || !rook_squares.contains(&mv.to())
+                || target != Piece::new(PieceType::Rook, stm)
+                || (pos.occupied() & between).count() > 0
+            {
+                return Err(invalid());
+            }
+        }
+        MoveType::EnPassant => {
+            let captured = pos.piece_at(Square::new(mv.to().index() ^ 8));
+
+            if !is_pawn
+                || mv.to() != pos.ep_square()
+                || target != Piece::none()
+                || captured != Piece::new(PieceType::Pawn, !stm)
+            {
+                return Err(invalid());
+            }
+        }
+        MoveType::Normal | MoveType::Promotion => {
+            let on_last_rank = mv.to().rank() == Rank::FIRST || mv.to().rank() == Rank::EIGHTH;
+            let is_promotion = mv.mtype() == MoveType::Promotion;
+            let promoted = mv.promoted_piece();
+
+            if (target != Piece::none() && target.color() == stm)
+                || target.piece_type() == PieceType::King
+                || (is_pawn && (on_last_rank != is_promotion || !is_pawn_move(pos, mv)))
+                || (is_promotion
+                    && (!is_pawn
+                        || promoted.color() != stm
+                        || !matches!(
+                            promoted.piece_type(),
+                            PieceType::Knight
+                                | PieceType::Bishop
+                                | PieceType::Rook
+                                | PieceType::Queen
+                        )))
+            {
+                return Err(invalid());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn is_pawn_move(pos: &Position, mv: Move) -> bool {
+    let stm = pos.side_to_move();
+    let (forward, start_rank) = if stm == Color::White {
+        (FlatSquareOffset::new(0, 1), Rank::SECOND)
+    } else {
+        (FlatSquareOffset::new(0, -1), Rank::SEVENTH)
+    };
+
+    let empty = |sq: Square| pos.piece_at(sq) == Piece::none();
+
+    let sq_forward = mv.from() + forward;
+    let push = mv.to() == sq_forward && empty(sq_forward);
+    let double_push = mv.from().rank() == start_rank
+        && mv.to() == sq_forward + forward
+        && empty(sq_forward)
+        && empty(mv.to());
+    let capture = Attacks::pawn(stm, mv.from()).sq_set(mv.to()) && !empty(mv.to());
+
+    push || double_push || capture
+}
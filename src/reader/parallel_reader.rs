@@ -0,0 +1,481 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::iter::FusedIterator;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::{
+    rng::Rng, training_data_entry::TrainingDataEntry,
+    training_data_file::CompressedTrainingDataFile,
+};
+
+use super::{
+    chunk_decoder::ChunkDecoder,
+    skip_filter::SkipFilter,
+    training_data_reader::{CompressedReaderError, Result},
+};
+
+#[derive(Debug, Clone)]
+pub struct ParallelReaderOptions {
+    /// Number of threads decoding chunks
+    pub num_threads: usize,
+    /// Yield the batches in the order of the chunks in the file, otherwise
+    /// batches are yielded as soon as they are decoded
+    pub preserve_order: bool,
+    /// Maximum number of chunks read from the file but not yet yielded
+    pub max_chunks_in_flight: usize,
+    /// Skip entries matching this filter, the random skipping is seeded per
+    /// chunk so the entries don't depend on the number of threads
+    pub filter: Option<SkipFilter>,
+}
+
+impl Default for ParallelReaderOptions {
+    fn default() -> Self {
+        let num_threads = thread::available_parallelism().map_or(1, |n| n.get());
+
+        Self {
+            num_threads,
+            preserve_order: true,
+            max_chunks_in_flight: 2 * num_threads,
+            filter: None,
+        }
+    }
+}
+
+/// A chunk together with its position in the file
+type IndexedChunk = (usize, Vec<u8>);
+
+/// The decoded entries of one chunk
+#[derive(Debug)]
+struct Batch {
+    index: usize,
+    entries: Vec<TrainingDataEntry>,
+    error: Option<CompressedReaderError>,
+}
+
+/// Reads chunks on a background thread and decodes them on a pool of worker
+/// threads, every chunk is yielded as one batch of entries.
+///
+/// Dropping the reader stops the threads and waits for them. When reading
+/// from a blocking stream this waits until the current read returns.
+#[derive(Debug)]
+pub struct ParallelTrainingDataEntryReader {
+    batches: Receiver<Batch>,
+    // one token per chunk in flight, taken back once its batch is yielded
+    slots: Receiver<()>,
+    pending: BTreeMap<usize, Batch>,
+    next_index: usize,
+    preserve_order: bool,
+    pending_error: Option<CompressedReaderError>,
+    read_bytes: Arc<AtomicU64>,
+    file_size: u64,
+    is_end: bool,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl ParallelTrainingDataEntryReader {
+    pub fn new(path: &str, options: ParallelReaderOptions) -> Result<Self> {
+        let file_size = std::fs::metadata(path)?.len();
+
+        Ok(Self::with_file(
+            CompressedTrainingDataFile::open(path)?,
+            file_size,
+            options,
+        ))
+    }
+
+    /// Read from any stream, see [`super::training_data_reader::CompressedTrainingDataEntryReader::from_reader`]
+    pub fn from_reader<T: Read + Send + 'static>(
+        reader: T,
+        options: ParallelReaderOptions,
+    ) -> Self {
+        Self::with_file(CompressedTrainingDataFile::from_stream(reader), 0, options)
+    }
+
+    fn with_file<T: Read + Send + 'static>(
+        input_file: CompressedTrainingDataFile<T>,
+        file_size: u64,
+        options: ParallelReaderOptions,
+    ) -> Self {
+        let num_threads = options.num_threads.max(1);
+        let max_chunks_in_flight = options.max_chunks_in_flight.max(1);
+
+        let (chunk_sender, chunk_receiver) = mpsc::sync_channel(num_threads);
+        let (batch_sender, batches) = mpsc::sync_channel(max_chunks_in_flight);
+        let (slot_sender, slots) = mpsc::sync_channel(max_chunks_in_flight);
+        let read_bytes = Arc::new(AtomicU64::new(0));
+
+        let chunk_receiver = Arc::new(Mutex::new(chunk_receiver));
+        let mut threads = Vec::with_capacity(num_threads + 1);
+
+        for _ in 0..num_threads {
+            let chunk_receiver = Arc::clone(&chunk_receiver);
+            let batch_sender = batch_sender.clone();
+            let filter = options.filter.clone();
+
+            threads.push(thread::spawn(move || {
+                decode_chunks(chunk_receiver, batch_sender, filter)
+            }));
+        }
+
+        {
+            let read_bytes = Arc::clone(&read_bytes);
+
+            threads.push(thread::spawn(move || {
+                read_chunks(
+                    input_file,
+                    chunk_sender,
+                    batch_sender,
+                    slot_sender,
+                    read_bytes,
+                )
+            }));
+        }
+
+        Self {
+            batches,
+            slots,
+            pending: BTreeMap::new(),
+            next_index: 0,
+            preserve_order: options.preserve_order,
+            pending_error: None,
+            read_bytes,
+            file_size,
+            is_end: false,
+            threads,
+        }
+    }
+
+    /// Get the size of the file in bytes, 0 if unknown
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    /// Get how much of the file has been read so far, chunks that are still
+    /// being decoded are included
+    pub fn read_bytes(&self) -> u64 {
+        self.read_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Read the entries of the next chunk, returns [`CompressedReaderError::EndOfFile`]
+    /// if there are no chunks left.
+    ///
+    /// If a chunk turns out to be corrupt the entries before the corruption are
+    /// returned first, the error is returned by the following call.
+    pub fn next_batch(&mut self) -> Result<Vec<TrainingDataEntry>> {
+        if let Some(e) = self.pending_error.take() {
+            return Err(e);
+        }
+
+        loop {
+            let Some(batch) = self.receive_batch() else {
+                self.is_end = true;
+                return Err(CompressedReaderError::EndOfFile);
+            };
+
+            // the token was sent before the chunk was dispatched
+            let _ = self.slots.recv();
+
+            match batch.error {
+                Some(e) if batch.entries.is_empty() => return Err(e),
+                error if !batch.entries.is_empty() => {
+                    self.pending_error = error;
+                    return Ok(batch.entries);
+                }
+                // skip empty chunks
+                _ => continue,
+            }
+        }
+    }
+
+    fn receive_batch(&mut self) -> Option<Batch> {
+        if !self.preserve_order {
+            return self.batches.recv().ok();
+        }
+
+        loop {
+            if let Some(batch) = self.pending.remove(&self.next_index) {
+                self.next_index += 1;
+                return Some(batch);
+            }
+
+            let batch = self.batches.recv().ok()?;
+            self.pending.insert(batch.index, batch);
+        }
+    }
+}
+
+impl Iterator for ParallelTrainingDataEntryReader {
+    type Item = Result<Vec<TrainingDataEntry>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_end {
+            return None;
+        }
+
+        match self.next_batch() {
+            Err(CompressedReaderError::EndOfFile) => None,
+            batch => Some(batch),
+        }
+    }
+}
+
+impl FusedIterator for ParallelTrainingDataEntryReader {}
+
+impl Drop for ParallelTrainingDataEntryReader {
+    fn drop(&mut self) {
+        // closing both channels makes every blocked send fail, the workers
+        // then drop the chunk receiver which stops the thread reading chunks
+        drop(std::mem::replace(
+            &mut self.batches,
+            mpsc::sync_channel(0).1,
+        ));
+        drop(std::mem::replace(&mut self.slots, mpsc::sync_channel(0).1));
+        self.pending.clear();
+
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn read_chunks<T: Read>(
+    mut input_file: CompressedTrainingDataFile<T>,
+    chunks: SyncSender<IndexedChunk>,
+    batches: SyncSender<Batch>,
+    slots: SyncSender<()>,
+    read_bytes: Arc<AtomicU64>,
+) {
+    let mut index = 0;
+
+    while input_file.has_next_chunk() {
+        // blocks while too many chunks are in flight, fails once the reader is dropped
+        if slots.send(()).is_err() {
+            return;
+        }
+
+        match input_file.read_next_chunk() {
+            Ok(chunk) => {
+                read_bytes.store(input_file.read_bytes(), Ordering::Relaxed);
+
+                if chunks.send((index, chunk)).is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                let _ = batches.send(Batch {
+                    index,
+                    entries: Vec::new(),
+                    error: Some(e.into()),
+                });
+                return;
+            }
+        }
+
+        index += 1;
+    }
+}
+
+fn decode_chunks(
+    chunks: Arc<Mutex<Receiver<IndexedChunk>>>,
+    batches: SyncSender<Batch>,
+    filter: Option<SkipFilter>,
+) {
+    loop {
+        let received = chunks.lock().unwrap().recv();
+        let Ok((index, chunk)) = received else {
+            return;
+        };
+
+        let mut decoder = ChunkDecoder::new();
+        let mut entries = Vec::new();
+        let mut error = None;
+        let mut rng = Rng::new(
+            filter
+                .as_ref()
+                .map_or(0, |f| f.seed)
+                .wrapping_add(index as u64),
+        );
+
+        while decoder.has_next(&chunk) {
+            match decoder.next_entry(&chunk) {
+                Ok(entry) if filter.as_ref().is_some_and(|f| f.skips(&entry, &mut rng)) => {}
+                Ok(entry) => entries.push(entry),
+                Err(e) => {
+                    error = Some(e);
+                    break;
+                }
+            }
+        }
+
+        let batch = Batch {
+            index,
+            entries,
+            error,
+        };
+
+        if batches.send(batch).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::training_data_reader::CompressedTrainingDataEntryReader;
+    use std::io::Cursor;
+
+    fn read_all(data: &[u8]) -> Vec<TrainingDataEntry> {
+        let reader = CompressedTrainingDataEntryReader::from_reader(data).unwrap();
+        reader.map(|e| e.unwrap()).collect()
+    }
+
+    /// Several copies of ep1 with a distinct score per chunk to tell them apart
+    fn multi_chunk_binpack(num_chunks: usize) -> Vec<u8> {
+        let data = std::fs::read("./test/ep1.binpack").unwrap();
+        let mut result = Vec::new();
+
+        for i in 0..num_chunks {
+            let mut chunk = data.clone();
+            // the score of the stem is stored at bytes 26..28 of the chunk data
+            chunk[8 + 26..8 + 28].copy_from_slice(&((i as u16) << 1).to_be_bytes());
+            result.extend_from_slice(&chunk);
+        }
+
+        result
+    }
+
+    fn options(num_threads: usize, preserve_order: bool) -> ParallelReaderOptions {
+        ParallelReaderOptions {
+            num_threads,
+            preserve_order,
+            max_chunks_in_flight: 3,
+            filter: None,
+        }
+    }
+
+    #[test]
+    fn test_parallel_reader_preserves_order() {
+        let data = multi_chunk_binpack(32);
+
+        let reader = ParallelTrainingDataEntryReader::from_reader(
+            Cursor::new(data.clone()),
+            options(4, true),
+        );
+        let entries = reader.flat_map(|b| b.unwrap()).collect::<Vec<_>>();
+
+        assert_eq!(entries, read_all(&data));
+    }
+
+    #[test]
+    fn test_parallel_reader_relaxed_order() {
+        let data = multi_chunk_binpack(32);
+
+        let reader = ParallelTrainingDataEntryReader::from_reader(
+            Cursor::new(data.clone()),
+            options(4, false),
+        );
+        let mut batches = reader.map(|b| b.unwrap()).collect::<Vec<_>>();
+        batches.sort_by_key(|b| b[0].score);
+
+        let entries = batches.into_iter().flatten().collect::<Vec<_>>();
+        assert_eq!(entries, read_all(&data));
+    }
+
+    #[test]
+    fn test_parallel_reader_file() {
+        let reader =
+            ParallelTrainingDataEntryReader::new("./test/ep1.binpack", Default::default()).unwrap();
+        assert_eq!(reader.file_size(), 46);
+
+        let entries = reader.flat_map(|b| b.unwrap()).collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            read_all(&std::fs::read("./test/ep1.binpack").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_parallel_reader_filter() {
+        let data = multi_chunk_binpack(8);
+        let entries = read_all(&data);
+        let last_ply = entries.iter().map(|e| e.ply).max().unwrap();
+
+        let filter = SkipFilter {
+            min_ply: last_ply,
+            ..Default::default()
+        };
+        let options = ParallelReaderOptions {
+            filter: Some(filter),
+            ..options(4, true)
+        };
+
+        let reader = ParallelTrainingDataEntryReader::from_reader(Cursor::new(data), options);
+        let filtered = reader.flat_map(|b| b.unwrap()).collect::<Vec<_>>();
+
+        let expected = entries
+            .into_iter()
+            .filter(|e| e.ply >= last_ply)
+            .collect::<Vec<_>>();
+        assert_eq!(filtered, expected);
+    }
+
+    #[test]
+    fn test_parallel_reader_random_skip_is_deterministic() {
+        let data = multi_chunk_binpack(16);
+        let read = |num_threads| {
+            let options = ParallelReaderOptions {
+                filter: Some(SkipFilter {
+                    random_skip_probability: 0.5,
+                    seed: 3,
+                    ..Default::default()
+                }),
+                ..options(num_threads, true)
+            };
+
+            ParallelTrainingDataEntryReader::from_reader(Cursor::new(data.clone()), options)
+                .flat_map(|b| b.unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let entries = read(1);
+        assert!(!entries.is_empty() && entries.len() < read_all(&data).len());
+        assert_eq!(read(4), entries);
+    }
+
+    #[test]
+    fn test_parallel_reader_truncated_chunk() {
+        let mut data = multi_chunk_binpack(2);
+        data.truncate(data.len() - 1);
+
+        let mut reader =
+            ParallelTrainingDataEntryReader::from_reader(Cursor::new(data), options(2, true));
+
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_parallel_reader_drop_early() {
+        let data = multi_chunk_binpack(64);
+        let (done_sender, done) = mpsc::channel();
+
+        thread::spawn(move || {
+            let mut reader =
+                ParallelTrainingDataEntryReader::from_reader(Cursor::new(data), options(2, true));
+            assert!(reader.next().unwrap().is_ok());
+            assert_eq!(reader.threads.len(), 3);
+
+            // returns once all threads are joined
+            drop(reader);
+            done_sender.send(()).unwrap();
+        });
+
+        let finished = done.recv_timeout(std::time::Duration::from_secs(10));
+        assert!(finished.is_ok(), "the reader threads didn't finish");
+    }
+}
@@ -0,0 +1,56 @@
+/// The position of a [`super::training_data_reader::CompressedTrainingDataEntryReader`],
+/// resuming at a cursor yields the same next entry as the reader it was taken from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct ReaderCursor {
+    /// Offset of the current chunk header from the start of the binpack
+    pub chunk_offset: u64,
+    /// Offset of the current chain within the chunk data
+    pub offset_in_chunk: u32,
+    /// Number of entries already read from the current chain, 0 if the
+    /// next entry is the start of a chain
+    pub ply_in_chain: u32,
+    /// Number of movetext bits already read from the current chain
+    pub movetext_bit: u64,
+}
+
+impl ReaderCursor {
+    pub const SIZE: usize = 24;
+
+    /// Serialize the cursor as little endian values
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut buf = [0u8; Self::SIZE];
+        buf[0..8].copy_from_slice(&self.chunk_offset.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.offset_in_chunk.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.ply_in_chain.to_le_bytes());
+        buf[16..24].copy_from_slice(&self.movetext_bit.to_le_bytes());
+        buf
+    }
+
+    pub fn from_bytes(buf: &[u8; Self::SIZE]) -> Self {
+        Self {
+            chunk_offset: u64::from_le_bytes(buf[0..8].try_into().unwrap()),
+            offset_in_chunk: u32::from_le_bytes(buf[8..12].try_into().unwrap()),
+            ply_in_chain: u32::from_le_bytes(buf[12..16].try_into().unwrap()),
+            movetext_bit: u64::from_le_bytes(buf[16..24].try_into().unwrap()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_bytes_roundtrip() {
+        let cursor = ReaderCursor {
+            chunk_offset: 0x0102_0304_0506_0708,
+            offset_in_chunk: 1234,
+            ply_in_chain: 56,
+            movetext_bit: 789,
+        };
+
+        let bytes = cursor.to_bytes();
+        assert_eq!(bytes[0], 0x08);
+        assert_eq!(ReaderCursor::from_bytes(&bytes), cursor);
+    }
+}
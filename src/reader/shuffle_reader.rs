@@ -0,0 +1,287 @@
+use std::fs::File;
+use std::io::Read;
+use std::iter::FusedIterator;
+
+use crate::{rng::Rng, training_data_entry::TrainingDataEntry};
+
+use super::training_data_reader::{
+    CompressedReaderError, CompressedTrainingDataEntryReader, Result,
+};
+
+#[derive(Debug, Clone)]
+pub struct ShuffleOptions {
+    /// Number of entries kept in memory to pick the next entry from
+    pub buffer_size: usize,
+    pub seed: u64,
+    /// Pick a random file for every chunk, otherwise the files are read one after another
+    pub interleave_chunks: bool,
+}
+
+impl Default for ShuffleOptions {
+    fn default() -> Self {
+        Self {
+            buffer_size: 1 << 20,
+            seed: 0,
+            interleave_chunks: true,
+        }
+    }
+}
+
+/// Reads entries from one or more binpacks in random order. Entries are
+/// collected in a buffer and a random one of them is returned every time,
+/// so the order is only shuffled within the range of the buffer size.
+#[derive(Debug)]
+pub struct ShufflingReader<T = File> {
+    sources: Vec<CompressedTrainingDataEntryReader<T>>,
+    current: usize,
+    buffer: Vec<TrainingDataEntry>,
+    buffer_size: usize,
+    interleave_chunks: bool,
+    rng: Rng,
+}
+
+impl ShufflingReader<File> {
+    pub fn new(paths: &[&str], options: ShuffleOptions) -> Result<Self> {
+        let mut readers = Vec::with_capacity(paths.len());
+
+        for path in paths {
+            match CompressedTrainingDataEntryReader::new(path) {
+                Ok(reader) => readers.push(reader),
+                // an empty file has nothing to contribute
+                Err(CompressedReaderError::EndOfFile) => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(Self::from_readers(readers, options))
+    }
+}
+
+impl<T: Read> ShufflingReader<T> {
+    pub fn from_readers(
+        readers: Vec<CompressedTrainingDataEntryReader<T>>,
+        options: ShuffleOptions,
+    ) -> Self {
+        let buffer_size = options.buffer_size.max(1);
+
+        Self {
+            sources: readers,
+            current: 0,
+            buffer: Vec::with_capacity(buffer_size.min(1 << 20)),
+            buffer_size,
+            interleave_chunks: options.interleave_chunks,
+            rng: Rng::new(options.seed),
+        }
+    }
+
+    /// Read the next entry, returns [`CompressedReaderError::EndOfFile`]
+    /// if there are no entries left.
+    ///
+    /// Errors of the underlying readers are returned as soon as they occur.
+    pub fn next_entry(&mut self) -> Result<TrainingDataEntry> {
+        while self.buffer.len() < self.buffer_size {
+            match self.read_source() {
+                // the remaining entries of the source were all filtered out,
+                // it is dropped by the next read
+                Some(Err(CompressedReaderError::EndOfFile)) => continue,
+                Some(entry) => self.buffer.push(entry?),
+                None => break,
+            }
+        }
+
+        if self.buffer.is_empty() {
+            return Err(CompressedReaderError::EndOfFile);
+        }
+
+        let index = self.rng.below(self.buffer.len());
+        Ok(self.buffer.swap_remove(index))
+    }
+
+    pub fn has_next(&self) -> bool {
+        !self.buffer.is_empty() || self.sources.iter().any(|source| source.has_next())
+    }
+
+    fn read_source(&mut self) -> Option<Result<TrainingDataEntry>> {
+        self.sources.retain(|source| source.has_next());
+
+        if self.sources.is_empty() {
+            return None;
+        }
+
+        // sources are only switched between chunks, so all but the
+        // current one are always at the start of a chunk
+        if !self.interleave_chunks {
+            self.current = 0;
+        } else if self.current >= self.sources.len()
+            || is_at_chunk_start(&self.sources[self.current])
+        {
+            self.current = self.rng.below(self.sources.len());
+        }
+
+        Some(self.sources[self.current].next_entry())
+    }
+}
+
+fn is_at_chunk_start<T: Read>(reader: &CompressedTrainingDataEntryReader<T>) -> bool {
+    let cursor = reader.cursor();
+    cursor.offset_in_chunk == 0 && cursor.ply_in_chain == 0
+}
+
+impl<T: Read> Iterator for ShufflingReader<T> {
+    type Item = Result<TrainingDataEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_entry() {
+            Err(CompressedReaderError::EndOfFile) => None,
+            entry => Some(entry),
+        }
+    }
+}
+
+impl<T: Read> FusedIterator for ShufflingReader<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{reader::skip_filter::SkipFilter, training_data_entry::PackedTrainingDataEntry};
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    /// Copies of ep1 with a distinct score per chunk to tell them apart
+    fn write_chunks(first_score: u16, num_chunks: u16) -> NamedTempFile {
+        let data = std::fs::read("./test/ep1.binpack").unwrap();
+        let mut file = NamedTempFile::new().unwrap();
+
+        for score in first_score..first_score + num_chunks {
+            let mut chunk = data.clone();
+            chunk[8 + 26..8 + 28].copy_from_slice(&(score << 1).to_be_bytes());
+            file.write_all(&chunk).unwrap();
+        }
+
+        file.flush().unwrap();
+        file
+    }
+
+    fn read_all(paths: &[&str]) -> Vec<TrainingDataEntry> {
+        paths
+            .iter()
+            .flat_map(|path| CompressedTrainingDataEntryReader::new(path).unwrap())
+            .map(|e| e.unwrap())
+            .collect()
+    }
+
+    fn sorted(entries: &[TrainingDataEntry]) -> Vec<[u8; 32]> {
+        let mut packed = entries
+            .iter()
+            .map(|e| PackedTrainingDataEntry::pack_entry(e).data)
+            .collect::<Vec<_>>();
+        packed.sort();
+        packed
+    }
+
+    fn shuffle(paths: &[&str], options: ShuffleOptions) -> Vec<TrainingDataEntry> {
+        ShufflingReader::new(paths, options)
+            .unwrap()
+            .map(|e| e.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_shuffle_single_file() {
+        let file = write_chunks(0, 20);
+        let paths = [file.path().to_str().unwrap()];
+
+        let options = ShuffleOptions {
+            buffer_size: 16,
+            seed: 1,
+            interleave_chunks: false,
+        };
+
+        let expected = read_all(&paths);
+        let shuffled = shuffle(&paths, options.clone());
+
+        assert_ne!(shuffled, expected);
+        assert_eq!(sorted(&shuffled), sorted(&expected));
+        assert_eq!(shuffle(&paths, options.clone()), shuffled);
+
+        let other_seed = ShuffleOptions { seed: 2, ..options };
+        assert_ne!(shuffle(&paths, other_seed), shuffled);
+    }
+
+    #[test]
+    fn test_buffer_size_one_keeps_order() {
+        let a = write_chunks(0, 4);
+        let b = write_chunks(100, 4);
+        let paths = [a.path().to_str().unwrap(), b.path().to_str().unwrap()];
+
+        let options = ShuffleOptions {
+            buffer_size: 1,
+            seed: 0,
+            interleave_chunks: false,
+        };
+
+        assert_eq!(shuffle(&paths, options), read_all(&paths));
+    }
+
+    #[test]
+    fn test_filtered_source() {
+        let path = "./test/ep1.binpack";
+        let filter = |seed| SkipFilter {
+            random_skip_probability: 0.5,
+            seed,
+            ..Default::default()
+        };
+
+        for seed in [1, 6, 10, 15, 17, 18] {
+            let mut reader = CompressedTrainingDataEntryReader::new(path).unwrap();
+            reader.set_filter(filter(seed));
+            let expected = reader.map(|e| e.unwrap()).collect::<Vec<_>>();
+            assert!(!expected.is_empty());
+
+            let mut reader = CompressedTrainingDataEntryReader::new(path).unwrap();
+            reader.set_filter(filter(seed));
+            let options = ShuffleOptions {
+                buffer_size: 16,
+                ..Default::default()
+            };
+
+            let mut shuffled = ShufflingReader::from_readers(vec![reader], options);
+            let entries = shuffled.by_ref().map(|e| e.unwrap()).collect::<Vec<_>>();
+
+            assert_eq!(sorted(&entries), sorted(&expected), "seed {seed}");
+            assert!(shuffled.next().is_none());
+        }
+    }
+
+    #[test]
+    fn test_interleave_chunks() {
+        let a = write_chunks(0, 10);
+        let b = write_chunks(100, 10);
+        let paths = [a.path().to_str().unwrap(), b.path().to_str().unwrap()];
+
+        let options = ShuffleOptions {
+            buffer_size: 1,
+            seed: 3,
+            interleave_chunks: true,
+        };
+
+        let entries = shuffle(&paths, options);
+        assert_eq!(sorted(&entries), sorted(&read_all(&paths)));
+
+        // whole chunks are read at once, but the files take turns
+        let stem_scores = entries
+            .chunks(3)
+            .map(|chunk| chunk[0].score)
+            .collect::<Vec<_>>();
+
+        let mut expected_scores = (0..10).chain(100..110).collect::<Vec<_>>();
+        let mut sorted_scores = stem_scores.clone();
+        sorted_scores.sort();
+        expected_scores.sort();
+        assert_eq!(sorted_scores, expected_scores);
+
+        let sources = stem_scores.iter().map(|&s| s >= 100).collect::<Vec<_>>();
+        assert!(sources[..10].iter().any(|&from_b| from_b));
+        assert!(sources[..10].iter().any(|&from_b| !from_b));
+    }
+}
@@ -0,0 +1,188 @@
+use crate::{rng::Rng, training_data_entry::TrainingDataEntry};
+
+/// Score of positions without an evaluation
+pub const VALUE_NONE: i16 = 32002;
+
+/// Decides which entries a reader skips, the checks follow the skip
+/// predicate of Stockfish's `training_data_loader`. The default skips nothing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SkipFilter {
+    /// Skip entries with a lower ply, `early_fen_skipping = n` in Stockfish
+    /// corresponds to `min_ply = n + 1`
+    pub min_ply: u16,
+    /// Skip entries whose move captures a piece, en passant doesn't count
+    pub skip_captures: bool,
+    /// Skip entries where the side to move is in check
+    pub skip_in_check: bool,
+    /// Skip entries with an absolute score above this value
+    pub max_abs_score: Option<i16>,
+    /// Probability of skipping an entry at random, `random_fen_skipping = n`
+    /// in Stockfish corresponds to `n / (n + 1)`
+    pub random_skip_probability: f64,
+    /// Skip entries with a score of [`VALUE_NONE`]
+    pub skip_value_none: bool,
+    /// Seed of the random skipping
+    pub seed: u64,
+}
+
+impl Default for SkipFilter {
+    fn default() -> Self {
+        Self {
+            min_ply: 0,
+            skip_captures: false,
+            skip_in_check: false,
+            max_abs_score: None,
+            random_skip_probability: 0.0,
+            skip_value_none: false,
+            seed: 0,
+        }
+    }
+}
+
+impl SkipFilter {
+    /// The checks are done in the same order as in Stockfish, random
+    /// values are only drawn for entries that pass the earlier checks
+    pub(crate) fn skips(&self, entry: &TrainingDataEntry, rng: &mut Rng) -> bool {
+        if self.skip_value_none && entry.score == VALUE_NONE {
+            return true;
+        }
+
+        if entry.ply < self.min_ply {
+            return true;
+        }
+
+        if self.random_skip_probability > 0.0 && rng.next_f64() < self.random_skip_probability {
+            return true;
+        }
+
+        if self.skip_captures && entry.is_capturing_move() {
+            return true;
+        }
+
+        if self.skip_in_check && entry.is_in_check() {
+            return true;
+        }
+
+        self.max_abs_score
+            .is_some_and(|max| entry.score.unsigned_abs() > max.unsigned_abs())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::{coords::Square, piece::Piece, position::Position, r#move::Move};
+
+    const E2: Square = Square::new(12);
+    const E4: Square = Square::new(28);
+    const D5: Square = Square::new(35);
+
+    fn entry(pieces: &[(Piece, Square)], mv: Move, score: i16, ply: u16) -> TrainingDataEntry {
+        let mut pos = Position::new();
+        for &(pc, sq) in pieces {
+            pos.place(pc, sq);
+        }
+
+        TrainingDataEntry {
+            pos,
+            mv,
+            score,
+            ply,
+            result: 0,
+        }
+    }
+
+    fn quiet_entry(score: i16, ply: u16) -> TrainingDataEntry {
+        entry(
+            &[
+                (Piece::WHITE_KING, Square::E1),
+                (Piece::BLACK_KING, Square::E8),
+                (Piece::WHITE_PAWN, E2),
+            ],
+            Move::normal(E2, E4),
+            score,
+            ply,
+        )
+    }
+
+    #[test]
+    fn test_default_skips_nothing() {
+        let filter = SkipFilter::default();
+        let mut rng = Rng::new(0);
+
+        assert!(!filter.skips(&quiet_entry(VALUE_NONE, 0), &mut rng));
+        assert!(!filter.skips(&quiet_entry(-30000, 0), &mut rng));
+    }
+
+    #[test]
+    fn test_skip_by_score_and_ply() {
+        let filter = SkipFilter {
+            min_ply: 8,
+            max_abs_score: Some(1000),
+            skip_value_none: true,
+            ..Default::default()
+        };
+        let mut rng = Rng::new(0);
+
+        assert!(filter.skips(&quiet_entry(0, 7), &mut rng));
+        assert!(!filter.skips(&quiet_entry(0, 8), &mut rng));
+        assert!(!filter.skips(&quiet_entry(-1000, 8), &mut rng));
+        assert!(filter.skips(&quiet_entry(-1001, 8), &mut rng));
+        assert!(filter.skips(&quiet_entry(i16::MIN, 8), &mut rng));
+        assert!(filter.skips(&quiet_entry(VALUE_NONE, 8), &mut rng));
+    }
+
+    #[test]
+    fn test_skip_captures_and_checks() {
+        let filter = SkipFilter {
+            skip_captures: true,
+            skip_in_check: true,
+            ..Default::default()
+        };
+        let mut rng = Rng::new(0);
+
+        let capture = entry(
+            &[
+                (Piece::WHITE_KING, Square::E1),
+                (Piece::BLACK_KING, Square::E8),
+                (Piece::WHITE_PAWN, E4),
+                (Piece::BLACK_PAWN, D5),
+            ],
+            Move::normal(E4, D5),
+            0,
+            0,
+        );
+        assert!(capture.is_capturing_move());
+        assert!(filter.skips(&capture, &mut rng));
+
+        let check = entry(
+            &[
+                (Piece::WHITE_KING, Square::E1),
+                (Piece::BLACK_KING, Square::E8),
+                (Piece::BLACK_QUEEN, E2),
+            ],
+            Move::normal(Square::E1, E2),
+            0,
+            0,
+        );
+        assert!(check.is_in_check());
+        assert!(filter.skips(&check, &mut rng));
+
+        assert!(!filter.skips(&quiet_entry(0, 0), &mut rng));
+    }
+
+    #[test]
+    fn test_random_skip() {
+        let filter = SkipFilter {
+            random_skip_probability: 0.75,
+            ..Default::default()
+        };
+        let mut rng = Rng::new(5);
+
+        let skipped = (0..10000)
+            .filter(|_| filter.skips(&quiet_entry(0, 0), &mut rng))
+            .count();
+
+        assert!((7200..7800).contains(&skipped), "{skipped}");
+    }
+}
@@ -1,13 +1,20 @@
-use std::io::{self};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::iter::FusedIterator;
 use thiserror::Error;
 
 use crate::{
     binpack_error::BinpackError,
-    training_data_entry::{PackedTrainingDataEntry, TrainingDataEntry},
+    chess::{color::Color, piecetype::PieceType},
+    rng::Rng,
+    training_data_entry::TrainingDataEntry,
     training_data_file::CompressedTrainingDataFile,
 };
 
-use super::move_score_list_reader::PackedMoveScoreListReader;
+use super::{
+    chunk_decoder::ChunkDecoder, chunk_index::ChunkIndex, reader_cursor::ReaderCursor,
+    skip_filter::SkipFilter,
+};
 
 const SUGGESTED_CHUNK_SIZE: usize = 8192;
 
@@ -21,48 +28,143 @@ pub enum CompressedReaderError {
     EndOfFile,
     #[error("Binpack error: {0}")]
     BinpackError(#[from] BinpackError),
+    #[error("Invalid entry in chunk at offset {chunk_offset}, ply {ply}: {message}")]
+    InvalidEntry {
+        chunk_offset: u64,
+        ply: u16,
+        message: String,
+    },
 }
 
 pub type Result<T> = std::result::Result<T, CompressedReaderError>;
 
 #[derive(Debug)]
-pub struct CompressedTrainingDataEntryReader {
+pub struct CompressedTrainingDataEntryReader<T = File> {
     chunk: Vec<u8>,
-    movelist_reader: Option<OwnedMoveScoreListReader>,
-    input_file: CompressedTrainingDataFile,
-    offset: usize,
+    // offset of the current chunk header
+    chunk_offset: u64,
+    decoder: ChunkDecoder,
+    input_file: CompressedTrainingDataFile<T>,
     file_size: u64,
     is_end: bool,
+    pending_error: Option<CompressedReaderError>,
+    filter: Option<SkipFilter>,
+    filter_rng: Rng,
+    validate: bool,
 }
 
-#[derive(Debug)]
-struct OwnedMoveScoreListReader {
-    reader: PackedMoveScoreListReader<'static>,
+impl CompressedTrainingDataEntryReader<File> {
+    pub fn new(path: &str) -> Result<Self> {
+        let file_size = std::fs::metadata(path)?.len();
+
+        Self::with_file(CompressedTrainingDataFile::open(path)?, file_size)
+    }
+
+    /// Open a file and continue reading at `cursor`, the next entry is the
+    /// one that followed when the cursor was taken
+    pub fn resume(path: &str, cursor: &ReaderCursor) -> Result<Self> {
+        let mut reader = Self::new(path)?;
+        reader.seek_to_cursor(cursor)?;
+        Ok(reader)
+    }
 }
 
-impl CompressedTrainingDataEntryReader {
-    pub fn new(path: &str) -> Result<Self> {
+impl<T: Read + Seek> CompressedTrainingDataEntryReader<T> {
+    /// Read from a seekable stream starting at its current position,
+    /// the size is determined by seeking to the end of the stream.
+    pub fn from_seekable_reader(mut reader: T) -> Result<Self> {
+        let pos = reader.stream_position()?;
+        let len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(pos))?;
+
+        Self::with_file(
+            CompressedTrainingDataFile::from_stream(reader),
+            len.saturating_sub(pos),
+        )
+    }
+
+    /// Continue reading at the first entry of a chunk of `index`,
+    /// returns [`CompressedReaderError::EndOfFile`] if there is no such chunk
+    pub fn seek_to_chunk(&mut self, index: &ChunkIndex, chunk: usize) -> Result<()> {
+        let info = index
+            .chunks()
+            .get(chunk)
+            .ok_or(CompressedReaderError::EndOfFile)?;
+
+        self.input_file.seek_to(info.offset)?;
+        self.chunk.clear();
+        self.decoder = ChunkDecoder::new();
+        self.is_end = false;
+        self.pending_error = None;
+
+        self.fetch_next_chunk_if_needed()
+    }
+
+    /// Continue reading at a cursor taken from a reader of the same file
+    pub fn seek_to_cursor(&mut self, cursor: &ReaderCursor) -> Result<()> {
+        self.input_file.seek_to(cursor.chunk_offset)?;
+        self.is_end = false;
+        self.pending_error = None;
+
+        self.load_next_chunk()?;
+        self.decoder = ChunkDecoder::resume(
+            &self.chunk,
+            cursor.offset_in_chunk as usize,
+            cursor.ply_in_chain,
+            cursor.movetext_bit,
+        )?;
+
+        self.fetch_next_chunk_if_needed()
+    }
+
+    /// Continue reading at the entry with the given number, counted from the
+    /// start of the file. Returns [`CompressedReaderError::EndOfFile`] if the
+    /// file has fewer entries.
+    pub fn seek_to_entry(&mut self, index: &ChunkIndex, entry: u64) -> Result<()> {
+        let (chunk, skip) = index
+            .find_entry(entry)
+            .ok_or(CompressedReaderError::EndOfFile)?;
+
+        self.seek_to_chunk(index, chunk)?;
+
+        for _ in 0..skip {
+            self.next_unfiltered_entry()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: Read> CompressedTrainingDataEntryReader<T> {
+    /// Read from any stream like stdin, a pipe or a decompressor.
+    /// The size of such a stream is unknown, so [`Self::file_size`] returns 0.
+    pub fn from_reader(reader: T) -> Result<Self> {
+        Self::with_file(CompressedTrainingDataFile::from_stream(reader), 0)
+    }
+
+    fn with_file(input_file: CompressedTrainingDataFile<T>, file_size: u64) -> Result<Self> {
         let chunk = Vec::with_capacity(SUGGESTED_CHUNK_SIZE);
 
         let mut reader = Self {
             chunk,
-            movelist_reader: None,
-            input_file: CompressedTrainingDataFile::new(path, false)?,
-            offset: 0,
-            file_size: std::fs::metadata(path)?.len(),
+            chunk_offset: 0,
+            decoder: ChunkDecoder::new(),
+            input_file,
+            file_size,
             is_end: false,
+            pending_error: None,
+            filter: None,
+            filter_rng: Rng::new(0),
+            validate: false,
         };
 
         if !reader.input_file.has_next_chunk() {
             reader.is_end = true;
             return Err(CompressedReaderError::EndOfFile);
-        } else {
-            reader.chunk = match reader.input_file.read_next_chunk() {
-                Ok(chunk) => chunk,
-                Err(e) => return Err(CompressedReaderError::BinpackError(e)),
-            };
         }
 
+        reader.fetch_next_chunk_if_needed()?;
+
         Ok(reader)
     }
 
@@ -80,71 +182,572 @@ impl CompressedTrainingDataEntryReader {
         !self.is_end
     }
 
-    pub fn next(&mut self) -> TrainingDataEntry {
-        if let Some(ref mut reader) = self.movelist_reader {
-            let entry = reader.reader.next_entry();
+    /// Whether the next entry starts a new chain instead of continuing the
+    /// game of the previous entry
+    pub fn is_chain_start(&self) -> bool {
+        self.decoder.is_chain_start()
+    }
 
-            if !reader.reader.has_next() {
-                self.offset += reader.reader.num_read_bytes();
-                self.movelist_reader = None;
-                self.fetch_next_chunk_if_needed();
-            }
+    /// Get the position of the next entry, see [`Self::resume`]
+    pub fn cursor(&self) -> ReaderCursor {
+        let (offset_in_chunk, ply_in_chain, movetext_bit) = self.decoder.position();
 
-            return entry;
+        ReaderCursor {
+            chunk_offset: self.chunk_offset,
+            offset_in_chunk: offset_in_chunk as u32,
+            ply_in_chain,
+            movetext_bit,
         }
+    }
 
-        // Read packed entry
-        let mut packed = PackedTrainingDataEntry::default();
+    /// Skip all entries matching `filter` from now on. Since skipped entries
+    /// are only known once they are decoded, [`Self::has_next`] can return
+    /// true even if no further entry passes the filter.
+    pub fn set_filter(&mut self, filter: SkipFilter) {
+        self.filter_rng = Rng::new(filter.seed);
+        self.filter = Some(filter);
+    }
 
-        debug_assert!(
-            self.offset + std::mem::size_of::<PackedTrainingDataEntry>() <= self.chunk.len()
-        );
+    /// Check every decoded entry, returns [`CompressedReaderError::InvalidEntry`]
+    /// for an entry with an illegal move, a missing king or the side not to move
+    /// in check. Like other corrupt chunks, the rest of the chunk is skipped.
+    pub fn set_validate(&mut self, validate: bool) {
+        self.validate = validate;
+    }
 
-        packed.copy_from_slice(
-            &self.chunk[self.offset..self.offset + std::mem::size_of::<PackedTrainingDataEntry>()],
-        );
+    /// Read the next entry, returns [`CompressedReaderError::EndOfFile`]
+    /// if there are no entries left.
+    ///
+    /// If the current chunk turns out to be corrupt the error is returned and
+    /// the rest of the chunk is skipped, so reading can continue with the next chunk.
+    pub fn next_entry(&mut self) -> Result<TrainingDataEntry> {
+        loop {
+            let entry = self.next_unfiltered_entry()?;
 
-        self.offset += std::mem::size_of::<PackedTrainingDataEntry>();
+            match self.filter {
+                Some(ref filter) if filter.skips(&entry, &mut self.filter_rng) => continue,
+                _ => return Ok(entry),
+            }
+        }
+    }
 
-        // Read number of plies
-        let num_plies =
-            ((self.chunk[self.offset] as u16) << 8) | (self.chunk[self.offset + 1] as u16);
-        self.offset += 2;
+    fn next_unfiltered_entry(&mut self) -> Result<TrainingDataEntry> {
+        if let Some(e) = self.pending_error.take() {
+            self.is_end = true;
+            return Err(e);
+        }
 
-        // let entry = unpack_entry(&packed);
-        let entry = packed.unpack_entry();
+        if self.is_end {
+            return Err(CompressedReaderError::EndOfFile);
+        }
 
-        if num_plies > 0 {
-            let chunk_ref = &self.chunk[self.offset..];
+        self.read_entry().inspect_err(|_| self.skip_chunk())
+    }
 
-            // should be safe lol, someone rewrite this please
-            let reader = unsafe {
-                std::mem::transmute::<
-                    PackedMoveScoreListReader<'_>,
-                    PackedMoveScoreListReader<'static>,
-                >(PackedMoveScoreListReader::new(entry, chunk_ref, num_plies))
-            };
+    fn read_entry(&mut self) -> Result<TrainingDataEntry> {
+        let entry = self.decoder.next_entry(&self.chunk)?;
 
-            self.movelist_reader = Some(OwnedMoveScoreListReader { reader });
-        } else {
-            self.fetch_next_chunk_if_needed();
+        // before advancing, so the error refers to the chunk of the entry
+        if self.validate {
+            validate_entry(&entry).map_err(|message| CompressedReaderError::InvalidEntry {
+                chunk_offset: self.chunk_offset,
+                ply: entry.ply,
+                message: message.to_string(),
+            })?;
         }
 
-        entry
+        self.advance_chunk_if_needed();
+        Ok(entry)
+    }
+
+    /// Drop the rest of the current chunk and move on to the next one
+    fn skip_chunk(&mut self) {
+        self.decoder.skip(&self.chunk);
+        self.advance_chunk_if_needed();
     }
 
-    fn fetch_next_chunk_if_needed(&mut self) {
-        if self.offset + std::mem::size_of::<PackedTrainingDataEntry>() + 2 > self.chunk.len() {
-            if self.input_file.has_next_chunk() {
-                let chunk = self.input_file.read_next_chunk().unwrap();
-                self.chunk = chunk;
-                self.offset = 0;
-            } else {
+    /// Load the next chunk once the current one is exhausted, errors are
+    /// reported by the following read so the current entry isn't lost
+    fn advance_chunk_if_needed(&mut self) {
+        if let Err(e) = self.fetch_next_chunk_if_needed() {
+            self.pending_error = Some(e);
+        }
+    }
+
+    fn fetch_next_chunk_if_needed(&mut self) -> Result<()> {
+        while !self.decoder.has_next(&self.chunk) {
+            if !self.input_file.has_next_chunk() {
                 self.is_end = true;
+                break;
             }
+
+            self.load_next_chunk()?;
+            self.decoder = ChunkDecoder::new();
         }
+
+        Ok(())
+    }
+
+    fn load_next_chunk(&mut self) -> Result<()> {
+        self.chunk_offset = self.input_file.read_bytes();
+        self.chunk = self.input_file.read_next_chunk()?;
+        Ok(())
     }
 }
 
+fn validate_entry(entry: &TrainingDataEntry) -> std::result::Result<(), &'static str> {
+    let pos = &entry.pos;
+
+    for color in [Color::White, Color::Black] {
+        if pos.pieces_bb_color(color, PieceType::King).count() != 1 {
+            return Err("Position without exactly one king per side");
+        }
+    }
+
+    if pos.is_checked(!pos.side_to_move()) {
+        return Err("Side not to move is in check");
+    }
+
+    if !pos.is_legal(entry.mv) {
+        return Err("Illegal move");
+    }
+
+    Ok(())
+}
+
+impl<T: Read> Iterator for CompressedTrainingDataEntryReader<T> {
+    type Item = Result<TrainingDataEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.has_next() {
+            return None;
+        }
+
+        match self.next_entry() {
+            // the remaining entries were all skipped
+            Err(CompressedReaderError::EndOfFile) => None,
+            entry => Some(entry),
+        }
+    }
+}
+
+impl<T: Read> FusedIterator for CompressedTrainingDataEntryReader<T> {}
+
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use crate::{
+        chess::{coords::Square, piece::Piece, position::Position, r#move::Move},
+        convert::test_util,
+        writer::training_data_writer::CompressedTrainingDataEntryWriter,
+    };
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn ep1_chunk() -> Vec<u8> {
+        std::fs::read("./test/ep1.binpack").unwrap()
+    }
+
+    fn chunk(data: &[u8]) -> Vec<u8> {
+        let mut chunk = b"BINP".to_vec();
+        chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(data);
+        chunk
+    }
+
+    fn write_file(data: &[u8]) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(data).unwrap();
+        file.flush().unwrap();
+        file
+    }
+
+    fn read_all(file: &NamedTempFile) -> Vec<Result<TrainingDataEntry>> {
+        CompressedTrainingDataEntryReader::new(file.path().to_str().unwrap())
+            .unwrap()
+            .collect()
+    }
+
+    #[test]
+    fn test_reader_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<CompressedTrainingDataEntryReader>();
+    }
+
+    #[test]
+    fn test_missing_file_is_not_created() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("missing.binpack");
+
+        let reader = CompressedTrainingDataEntryReader::new(path.to_str().unwrap());
+        assert!(matches!(reader, Err(CompressedReaderError::Io(_))));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let data = ep1_chunk();
+
+        let reader = CompressedTrainingDataEntryReader::from_reader(data.as_slice()).unwrap();
+        assert_eq!(reader.file_size(), 0);
+
+        let entries = reader.collect::<Result<Vec<_>>>().unwrap();
+        let expected = CompressedTrainingDataEntryReader::new("./test/ep1.binpack")
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn test_from_seekable_reader() {
+        let mut data = ep1_chunk();
+        data.extend_from_slice(&ep1_chunk());
+
+        let mut cursor = std::io::Cursor::new(data);
+        cursor.set_position(ep1_chunk().len() as u64);
+
+        let mut reader = CompressedTrainingDataEntryReader::from_seekable_reader(cursor).unwrap();
+        assert_eq!(reader.file_size(), ep1_chunk().len() as u64);
+
+        let mut count = 0;
+        while reader.has_next() {
+            reader.next_entry().unwrap();
+            count += 1;
+        }
+
+        assert_eq!(count, 3);
+        assert_eq!(reader.read_bytes(), reader.file_size());
+    }
+
+    /// Copies of ep1 with a distinct score per chunk to tell them apart
+    fn distinct_chunks(num_chunks: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        for i in 0..num_chunks {
+            let mut chunk = ep1_chunk();
+            chunk[8 + 26..8 + 28].copy_from_slice(&((i as u16) << 1).to_be_bytes());
+            data.extend_from_slice(&chunk);
+        }
+
+        data
+    }
+
+    #[test]
+    fn test_seek_to_entry() {
+        let file = write_file(&distinct_chunks(4));
+        let path = file.path().to_str().unwrap();
+
+        let entries = read_all(&file)
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        let index = ChunkIndex::build(path).unwrap();
+        assert_eq!(index.num_entries(), entries.len() as u64);
+
+        let mut reader = CompressedTrainingDataEntryReader::new(path).unwrap();
+
+        for n in (0..entries.len()).rev() {
+            reader.seek_to_entry(&index, n as u64).unwrap();
+            assert_eq!(reader.next_entry().unwrap(), entries[n]);
+        }
+
+        reader.seek_to_chunk(&index, 2).unwrap();
+        let rest = reader.by_ref().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(rest, entries[2 * 3..]);
+
+        assert!(matches!(
+            reader.seek_to_entry(&index, entries.len() as u64),
+            Err(CompressedReaderError::EndOfFile)
+        ));
+        assert!(matches!(
+            reader.seek_to_chunk(&index, 4),
+            Err(CompressedReaderError::EndOfFile)
+        ));
+    }
+
+    #[test]
+    fn test_seek_in_seekable_reader() {
+        let data = distinct_chunks(3);
+        let index = ChunkIndex::from_reader(data.as_slice()).unwrap();
+
+        let mut prefixed = b"garbage".to_vec();
+        prefixed.extend_from_slice(&data);
+        let mut cursor = std::io::Cursor::new(prefixed);
+        cursor.set_position(7);
+
+        let mut reader = CompressedTrainingDataEntryReader::from_seekable_reader(cursor).unwrap();
+        let entries = reader.by_ref().collect::<Result<Vec<_>>>().unwrap();
+
+        reader.seek_to_entry(&index, 4).unwrap();
+        let rest = reader.collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(rest, entries[4..]);
+    }
+
+    #[test]
+    fn test_resume_at_cursor() {
+        let file = write_file(&distinct_chunks(3));
+        let path = file.path().to_str().unwrap();
+
+        let mut reader = CompressedTrainingDataEntryReader::new(path).unwrap();
+        let mut cursors = vec![reader.cursor()];
+        let mut entries = Vec::new();
+
+        while reader.has_next() {
+            entries.push(reader.next_entry().unwrap());
+            cursors.push(reader.cursor());
+        }
+
+        for (n, cursor) in cursors.iter().enumerate() {
+            let cursor = ReaderCursor::from_bytes(&cursor.to_bytes());
+            let reader = CompressedTrainingDataEntryReader::resume(path, &cursor).unwrap();
+
+            let rest = reader.collect::<Result<Vec<_>>>().unwrap();
+            assert_eq!(rest, entries[n..]);
+        }
+    }
+
+    #[test]
+    fn test_resume_at_invalid_cursor() {
+        let file = write_file(&ep1_chunk());
+        let path = file.path().to_str().unwrap();
+
+        let mut reader = CompressedTrainingDataEntryReader::new(path).unwrap();
+        reader.next_entry().unwrap();
+        reader.next_entry().unwrap();
+
+        let mut cursor = reader.cursor();
+        assert_eq!(cursor.ply_in_chain, 2);
+
+        cursor.movetext_bit += 1;
+        assert!(matches!(
+            CompressedTrainingDataEntryReader::resume(path, &cursor),
+            Err(CompressedReaderError::InvalidFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_filter() {
+        let file = write_file(&distinct_chunks(4));
+        let path = file.path().to_str().unwrap();
+
+        let entries = read_all(&file)
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        let index = ChunkIndex::build(path).unwrap();
+
+        let mut reader = CompressedTrainingDataEntryReader::new(path).unwrap();
+        let last_ply = entries.iter().map(|e| e.ply).max().unwrap();
+        reader.set_filter(SkipFilter {
+            min_ply: last_ply,
+            ..Default::default()
+        });
+
+        let filtered = reader.by_ref().collect::<Result<Vec<_>>>().unwrap();
+        let expected = entries
+            .iter()
+            .filter(|e| e.ply >= last_ply)
+            .copied()
+            .collect::<Vec<_>>();
+        assert_eq!(filtered, expected);
+
+        // seeking counts skipped entries as well
+        reader.seek_to_entry(&index, 1).unwrap();
+        assert_eq!(reader.next_entry().unwrap(), expected[0]);
+
+        reader.set_filter(SkipFilter {
+            min_ply: u16::MAX,
+            ..Default::default()
+        });
+        reader.seek_to_entry(&index, 0).unwrap();
+        assert!(reader.has_next());
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_iterator() {
+        let reader = CompressedTrainingDataEntryReader::new("./test/ep1.binpack").unwrap();
+
+        let entries = reader.collect::<Result<Vec<_>>>().unwrap();
+
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries.iter().map(|e| e.score as i64).sum::<i64>(), -167);
+        assert_eq!(entries[1].ply, entries[0].ply + 1);
+    }
+
+    #[test]
+    fn test_iterator_adaptors() {
+        let reader = CompressedTrainingDataEntryReader::new("./test/ep1.binpack").unwrap();
+
+        let plies = reader
+            .filter_map(|e| e.ok())
+            .skip(1)
+            .take(5)
+            .map(|e| e.ply)
+            .collect::<Vec<_>>();
+
+        assert_eq!(plies.len(), 2);
+    }
+
+    #[test]
+    fn test_next_entry_after_end() {
+        let mut reader = CompressedTrainingDataEntryReader::new("./test/ep1.binpack").unwrap();
+
+        for _ in 0..3 {
+            assert!(reader.next_entry().is_ok());
+        }
+
+        assert!(!reader.has_next());
+        assert!(reader.next().is_none());
+        assert!(matches!(
+            reader.next_entry(),
+            Err(CompressedReaderError::EndOfFile)
+        ));
+    }
+
+    #[test]
+    fn test_truncated_movetext() {
+        let mut data = ep1_chunk();
+        data.truncate(data.len() - 3);
+        let chunk_size = (data.len() - 8) as u32;
+        data[4..8].copy_from_slice(&chunk_size.to_le_bytes());
+
+        let file = write_file(&data);
+        let results = read_all(&file);
+
+        assert!(results[0].is_ok());
+        assert!(matches!(
+            results.last(),
+            Some(Err(CompressedReaderError::InvalidFormat(_)))
+        ));
+    }
+
+    #[test]
+    fn test_truncated_chunk() {
+        let mut data = ep1_chunk();
+        data.extend_from_slice(&ep1_chunk()[..20]);
+
+        let file = write_file(&data);
+        let results = read_all(&file);
+
+        assert_eq!(results.len(), 4);
+        assert!(results[..3].iter().all(|r| r.is_ok()));
+        assert!(matches!(
+            results[3],
+            Err(CompressedReaderError::BinpackError(
+                BinpackError::InvalidFormat(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_bad_magic_mid_file() {
+        let mut data = ep1_chunk();
+        data.extend_from_slice(b"XXXX\x10\x00\x00\x00");
+        data.extend_from_slice(&[0u8; 16]);
+
+        let file = write_file(&data);
+        let results = read_all(&file);
+
+        assert_eq!(results.len(), 4);
+        assert!(results[..3].iter().all(|r| r.is_ok()));
+        assert!(matches!(
+            results[3],
+            Err(CompressedReaderError::BinpackError(
+                BinpackError::InvalidMagic
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_skip_corrupt_chunk() {
+        let mut data = chunk(&[0xFF; 40]);
+        data.extend_from_slice(&ep1_chunk());
+
+        let file = write_file(&data);
+        let results = read_all(&file);
+
+        assert_eq!(results.len(), 4);
+        assert!(results[0].is_err());
+        assert!(results[1..].iter().all(|r| r.is_ok()));
+    }
+
+    #[test]
+    fn test_corrupt_movetext() {
+        let mut data = ep1_chunk();
+        let len = data.len();
+        data[len - 4..].copy_from_slice(&[0xFF; 4]);
+
+        let file = write_file(&data);
+        let results = read_all(&file);
+
+        assert!(results[0].is_ok());
+        assert!(results.iter().any(|r| r.is_err()));
+    }
+
+    fn entry(fen: &str, uci: &str, ply: u16) -> TrainingDataEntry {
+        let entry = test_util::entry(fen, 0, 0);
+
+        TrainingDataEntry {
+            mv: Move::from_uci(&entry.pos, uci).unwrap(),
+            ply,
+            ..entry
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let startpos = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let bad = NamedTempFile::new().unwrap();
+        let bad_path = bad.path().to_str().unwrap();
+
+        let mut writer = CompressedTrainingDataEntryWriter::new(bad_path, false).unwrap();
+        writer.write_entry(&entry(startpos, "e2e4", 0)).unwrap();
+        writer.write_entry(&entry(startpos, "e2e5", 7)).unwrap();
+        writer.write_entry(&entry(startpos, "d2d4", 0)).unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+
+        let mut data = ep1_chunk();
+        data.extend_from_slice(&std::fs::read(bad_path).unwrap());
+        let file = write_file(&data);
+
+        assert!(read_all(&file).iter().all(|r| r.is_ok()));
+
+        let mut reader =
+            CompressedTrainingDataEntryReader::new(file.path().to_str().unwrap()).unwrap();
+        reader.set_validate(true);
+        let results = reader.collect::<Vec<_>>();
+
+        // the rest of the chunk is skipped after the illegal move
+        assert_eq!(results.len(), 5);
+        assert!(results[..4].iter().all(|r| r.is_ok()));
+        assert!(matches!(
+            &results[4],
+            Err(CompressedReaderError::InvalidEntry { chunk_offset, ply: 7, .. })
+                if *chunk_offset == ep1_chunk().len() as u64
+        ));
+    }
+
+    #[test]
+    fn test_validate_entry() {
+        assert!(validate_entry(&entry("4k3/8/8/8/8/8/8/4K3 w - - 0 1", "e1d2", 0)).is_ok());
+        assert!(validate_entry(&entry("4k3/8/8/8/8/8/8/4K2r w - - 0 1", "e1f1", 0)).is_err());
+
+        let mut in_check = entry("4k3/8/8/8/8/8/8/4K2r w - - 0 1", "e1e2", 0);
+        in_check.pos.set_side_to_move(Color::Black);
+        in_check.mv = Move::from_uci(&in_check.pos, "e8d8").unwrap();
+        assert!(validate_entry(&in_check).is_err());
+
+        let mut no_king = TrainingDataEntry {
+            pos: Position::new(),
+            ..in_check
+        };
+        no_king
+            .pos
+            .place(Piece::new(PieceType::King, Color::White), Square::E1);
+        assert!(validate_entry(&no_king).is_err());
+    }
+}
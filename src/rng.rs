@@ -0,0 +1,71 @@
+/// Small xorshift64* generator, the sequence only depends on the seed so
+/// shuffling and sampling are reproducible across platforms and versions.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // splitmix64 of the seed, the state must never be zero
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+
+        Self {
+            state: if z == 0 { 1 } else { z },
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniform number in `0..n`, `n` must not be 0
+    pub fn below(&mut self, n: usize) -> usize {
+        ((self.next_u64() as u128 * n as u128) >> 64) as usize
+    }
+
+    /// Uniform number in `0.0..1.0`
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rng_is_deterministic() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        let mut c = Rng::new(43);
+
+        let a = (0..16).map(|_| a.next_u64()).collect::<Vec<_>>();
+        let b = (0..16).map(|_| b.next_u64()).collect::<Vec<_>>();
+        let c = (0..16).map(|_| c.next_u64()).collect::<Vec<_>>();
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn test_rng_ranges() {
+        let mut rng = Rng::new(0);
+        let mut seen = [false; 7];
+
+        for _ in 0..1000 {
+            seen[rng.below(7)] = true;
+
+            let f = rng.next_f64();
+            assert!((0.0..1.0).contains(&f));
+        }
+
+        assert!(seen.iter().all(|&s| s));
+    }
+}
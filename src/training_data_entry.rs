@@ -1,11 +1,14 @@
 use crate::{
-    arithmetic::unsigned_to_signed,
-    chess::{position::Position, r#move::Move},
+    arithmetic::{signed_to_unsigned, unsigned_to_signed},
+    chess::{piece::Piece, position::Position, r#move::Move},
     compressed_move::CompressedMove,
     compressed_position::CompressedPosition,
 };
 
-#[derive(Debug, Clone, Copy)]
+/// Largest ply that fits into the 14 bits of a binpack stem
+pub const MAX_PLY: u16 = 0x3FFF;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct TrainingDataEntry {
     pub pos: Position,
     pub mv: Move,
@@ -14,6 +17,28 @@ pub struct TrainingDataEntry {
     pub result: i16,
 }
 
+impl TrainingDataEntry {
+    /// Whether `other` is the entry that directly follows this one in a game,
+    /// i.e. it can be stored in the movetext of this entry's chain.
+    pub fn is_continuation(&self, other: &TrainingDataEntry) -> bool {
+        self.result == -other.result
+            && self.ply.checked_add(1) == Some(other.ply)
+            && self.pos.after_move(self.mv) == other.pos
+    }
+
+    /// Whether the move captures a piece, like in the C++ implementation
+    /// en passant captures don't count
+    pub fn is_capturing_move(&self) -> bool {
+        let captured = self.pos.piece_at(self.mv.to());
+
+        captured != Piece::none() && captured.color() != self.pos.piece_at(self.mv.from()).color()
+    }
+
+    pub fn is_in_check(&self) -> bool {
+        self.pos.is_checked(self.pos.side_to_move())
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct PackedTrainingDataEntry {
     pub data: [u8; 32],
@@ -28,6 +53,39 @@ impl PackedTrainingDataEntry {
         ((self.data[offset] as u16) << 8) | (self.data[offset + 1] as u16)
     }
 
+    pub fn pack_entry(entry: &TrainingDataEntry) -> Self {
+        let mut packed = Self::default();
+        let mut offset = 0;
+
+        // Write compressed position
+        let compressed_pos = CompressedPosition::compress(&entry.pos);
+        compressed_pos.write_to_big_endian(&mut packed.data[offset..]);
+        offset += std::mem::size_of::<CompressedPosition>();
+
+        // Write compressed move
+        let compressed_move = entry.mv.compress();
+        compressed_move.write_to_big_endian(&mut packed.data[offset..]);
+        offset += std::mem::size_of::<CompressedMove>();
+
+        // Write score
+        packed.write_u16_be(offset, signed_to_unsigned(entry.score));
+        offset += 2;
+
+        // Write ply and result (packed together)
+        let pr = entry.ply | (signed_to_unsigned(entry.result) << 14);
+        packed.write_u16_be(offset, pr);
+        offset += 2;
+
+        // Write rule50 counter
+        packed.write_u16_be(offset, entry.pos.rule50_counter());
+
+        packed
+    }
+
+    pub fn write_u16_be(&mut self, offset: usize, value: u16) {
+        self.data[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+    }
+
     pub fn unpack_entry(&self) -> TrainingDataEntry {
         let mut offset = 0;
 
@@ -66,3 +124,63 @@ impl PackedTrainingDataEntry {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::training_data_reader::CompressedTrainingDataEntryReader;
+
+    #[test]
+    fn test_pack_unpack_corpus() {
+        let reader = CompressedTrainingDataEntryReader::new("./test/ep1.binpack").unwrap();
+
+        for entry in reader {
+            let entry = entry.unwrap();
+
+            let packed = PackedTrainingDataEntry::pack_entry(&entry);
+            let unpacked = packed.unpack_entry();
+
+            assert_eq!(unpacked, entry);
+            assert_eq!(unpacked.pos.rule50_counter(), entry.pos.rule50_counter());
+            assert_eq!(unpacked.pos.fen(), entry.pos.fen());
+        }
+    }
+
+    #[test]
+    fn test_pack_unpack_fields() {
+        let mut reader = CompressedTrainingDataEntryReader::new("./test/ep1.binpack").unwrap();
+        let base = reader.next_entry().unwrap();
+
+        for (score, ply, result) in [
+            (0, 0, 0),
+            (-32002, 1, -1),
+            (32002, 0x3FFF, 1),
+            (i16::MIN, 200, 0),
+            (i16::MAX, 201, -1),
+        ] {
+            let mut entry = base;
+            entry.score = score;
+            entry.ply = ply;
+            entry.result = result;
+            entry.pos.set_ply(ply);
+            entry.pos.set_rule50_counter(ply % 100);
+
+            let unpacked = PackedTrainingDataEntry::pack_entry(&entry).unpack_entry();
+
+            assert_eq!(unpacked, entry);
+            assert_eq!(unpacked.pos.rule50_counter(), entry.pos.rule50_counter());
+        }
+    }
+
+    #[test]
+    fn test_is_continuation_at_max_ply() {
+        let mut reader = CompressedTrainingDataEntryReader::new("./test/ep1.binpack").unwrap();
+        let mut first = reader.next_entry().unwrap();
+        let mut second = reader.next_entry().unwrap();
+        assert!(first.is_continuation(&second));
+
+        first.ply = u16::MAX;
+        second.ply = 0;
+        assert!(!first.is_continuation(&second));
+    }
+}
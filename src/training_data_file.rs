@@ -1,11 +1,11 @@
 use std::fs::{File, OpenOptions};
-use std::io::{self, Read, Seek, SeekFrom};
+use std::io::{self, Read, Seek, SeekFrom, Write};
 
 use crate::binpack_error::{BinpackError, Result};
 
 // use crate::reader::move_score_list_reader::BinpackError;
 
-const HEADER_SIZE: usize = 8;
+pub(crate) const HEADER_SIZE: usize = 8;
 
 const KI_B: u32 = 1024;
 const MI_B: u32 = 1024 * KI_B;
@@ -17,49 +17,102 @@ struct Header {
     chunk_size: u32,
 }
 
+/// A binpack split into `BINP` chunks, stored in a file or any other stream.
 #[derive(Debug)]
-pub struct CompressedTrainingDataFile {
-    file: File,
+pub struct CompressedTrainingDataFile<T = File> {
+    file: T,
     read_bytes: u64,
+    // header bytes read ahead by `has_next_chunk`
+    peeked: [u8; HEADER_SIZE],
+    peeked_len: usize,
 }
 
-impl CompressedTrainingDataFile {
-    pub fn new(path: &str, append: bool) -> io::Result<Self> {
+impl CompressedTrainingDataFile<File> {
+    /// Open an existing file for reading only, the file is never created or modified
+    pub fn open(path: &str) -> io::Result<Self> {
+        let file = File::open(path)?;
+
+        Ok(Self::from_stream(file))
+    }
+
+    /// Open a file for writing, the file is truncated unless `append` is set
+    pub fn create(path: &str, append: bool) -> io::Result<Self> {
         let file = OpenOptions::new()
-            .read(true)
             .write(true)
             .create(true)
+            .truncate(!append)
             .append(append)
             .open(path)?;
 
-        Ok(Self {
+        Ok(Self::from_stream(file))
+    }
+
+    /// Flush all written chunks and wait until they have reached the disk
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file.sync_data()
+    }
+}
+
+impl<T> CompressedTrainingDataFile<T> {
+    /// Use any stream as the underlying storage, reading requires `T: Read`
+    /// and writing requires `T: Write`
+    pub fn from_stream(file: T) -> Self {
+        Self {
             file,
             read_bytes: 0,
-        })
+            peeked: [0u8; HEADER_SIZE],
+            peeked_len: 0,
+        }
     }
 
-    // pub fn append(&mut self, data: &[u8]) -> io::Result<()> {
-    //     let header = Header {
-    //         chunk_size: data.len() as u32,
-    //     };
-    //     self.write_chunk_header(&header)?;
-    //     self.file.write_all(data)?;
-    //     Ok(())
-    // }
-
     pub fn read_bytes(&self) -> u64 {
         self.read_bytes
     }
+}
 
+impl<T: Write> CompressedTrainingDataFile<T> {
+    /// Write `data` as a single chunk at the end of the file
+    pub fn append(&mut self, data: &[u8]) -> Result<()> {
+        if data.len() > MAX_CHUNK_SIZE as usize {
+            return Err(BinpackError::InvalidFormat(
+                "Chunk size larger than supported.".to_string(),
+            ));
+        }
+
+        let header = Header {
+            chunk_size: data.len() as u32,
+        };
+        self.write_chunk_header(&header)?;
+        self.file.write_all(data)?;
+        Ok(())
+    }
+
+    fn write_chunk_header(&mut self, header: &Header) -> io::Result<()> {
+        let mut buf = [0u8; HEADER_SIZE];
+        buf[0] = b'B';
+        buf[1] = b'I';
+        buf[2] = b'N';
+        buf[3] = b'P';
+        buf[4] = (header.chunk_size & 0xFF) as u8;
+        buf[5] = ((header.chunk_size >> 8) & 0xFF) as u8;
+        buf[6] = ((header.chunk_size >> 16) & 0xFF) as u8;
+        buf[7] = ((header.chunk_size >> 24) & 0xFF) as u8;
+        self.file.write_all(&buf)
+    }
+}
+
+impl<T: Read> CompressedTrainingDataFile<T> {
     pub fn has_next_chunk(&mut self) -> bool {
-        if let Ok(pos) = self.file.stream_position() {
-            if let Ok(len) = self.file.seek(SeekFrom::End(0)) {
-                if self.file.seek(SeekFrom::Start(pos)).is_ok() {
-                    return pos < len;
-                }
+        if self.peeked_len == 0 {
+            match read_up_to(&mut self.file, &mut self.peeked) {
+                Ok(n) => self.peeked_len = n,
+                // let read_next_chunk report the error
+                Err(_) => return true,
             }
         }
-        false
+
+        self.peeked_len > 0
     }
 
     pub fn read_next_chunk(&mut self) -> Result<Vec<u8>> {
@@ -67,56 +120,85 @@ impl CompressedTrainingDataFile {
 
         let mut data = vec![0u8; (header.chunk_size) as usize];
 
-        self.file.read_exact(&mut data)?;
+        let len = read_up_to(&mut self.file, &mut data)?;
+        self.read_bytes += len as u64;
 
-        self.read_bytes += header.chunk_size as u64;
+        if len < data.len() {
+            return Err(BinpackError::InvalidFormat(
+                "Chunk is truncated. Malformed file?".to_string(),
+            ));
+        }
 
         Ok(data)
     }
 
-    // fn write_chunk_header(&mut self, header: &Header) -> io::Result<()> {
-    //     let mut buf = [0u8; HEADER_SIZE];
-    //     buf[0] = b'B';
-    //     buf[1] = b'I';
-    //     buf[2] = b'N';
-    //     buf[3] = b'P';
-    //     buf[4] = (header.chunk_size & 0xFF) as u8;
-    //     buf[5] = ((header.chunk_size >> 8) & 0xFF) as u8;
-    //     buf[6] = ((header.chunk_size >> 16) & 0xFF) as u8;
-    //     buf[7] = ((header.chunk_size >> 24) & 0xFF) as u8;
-    //     self.file.write_all(&buf)
-    // }
-
     fn read_chunk_header(&mut self) -> Result<Header> {
-        let mut buf = [0u8; HEADER_SIZE];
-
-        match self.file.read_exact(&mut buf) {
-            Ok(_) => (),
-            Err(_) => return Err(BinpackError::InvalidMagic),
-        }
+        let mut buf = self.peeked;
+        let peeked_len = std::mem::take(&mut self.peeked_len);
 
-        self.read_bytes += HEADER_SIZE as u64;
+        let len = peeked_len + read_up_to(&mut self.file, &mut buf[peeked_len..])?;
+        self.read_bytes += len as u64;
 
-        if &buf[0..4] != b"BINP" {
+        if len < HEADER_SIZE {
             return Err(BinpackError::InvalidMagic);
         }
 
-        let chunk_size = u32::from_le_bytes(buf[4..8].try_into().unwrap());
+        Ok(Header {
+            chunk_size: parse_chunk_header(&buf)?,
+        })
+    }
+}
 
-        if chunk_size > MAX_CHUNK_SIZE {
-            return Err(BinpackError::InvalidFormat(
-                "Chunk size larger than supported. Malformed file?".to_string(),
-            ));
-        }
+/// Validate a chunk header and return the size of the chunk following it
+pub(crate) fn parse_chunk_header(buf: &[u8; HEADER_SIZE]) -> Result<u32> {
+    if &buf[0..4] != b"BINP" {
+        return Err(BinpackError::InvalidMagic);
+    }
+
+    let chunk_size = u32::from_le_bytes(buf[4..8].try_into().unwrap());
+
+    if chunk_size > MAX_CHUNK_SIZE {
+        return Err(BinpackError::InvalidFormat(
+            "Chunk size larger than supported. Malformed file?".to_string(),
+        ));
+    }
+
+    Ok(chunk_size)
+}
+
+impl<T: Read + Seek> CompressedTrainingDataFile<T> {
+    /// Continue reading at `offset`, counted from where reading started
+    pub fn seek_to(&mut self, offset: u64) -> io::Result<()> {
+        let position = self.read_bytes + self.peeked_len as u64;
+
+        self.file
+            .seek(SeekFrom::Current(offset as i64 - position as i64))?;
+        self.read_bytes = offset;
+        self.peeked_len = 0;
 
-        Ok(Header { chunk_size })
+        Ok(())
     }
 }
 
+/// Read until `buf` is full or the end of the stream is reached
+fn read_up_to<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(len)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use io::Write;
     use tempfile::NamedTempFile;
 
     fn create_test_file(data: &[u8]) -> NamedTempFile {
@@ -139,9 +221,34 @@ mod tests {
 
     #[test]
     fn test_new_file_creation() {
-        let temp_path = NamedTempFile::new().unwrap();
-        let file = CompressedTrainingDataFile::new(temp_path.path().to_str().unwrap(), false);
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("new.binpack");
+        let file = CompressedTrainingDataFile::create(path.to_str().unwrap(), false);
         assert!(file.is_ok());
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn test_open_does_not_create() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("missing.binpack");
+
+        let file = CompressedTrainingDataFile::open(path.to_str().unwrap());
+        assert!(file.is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_open_read_only_file() {
+        let temp_file = create_test_file(b"Read only");
+
+        let mut permissions = std::fs::metadata(temp_file.path()).unwrap().permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(temp_file.path(), permissions).unwrap();
+
+        let mut file =
+            CompressedTrainingDataFile::open(temp_file.path().to_str().unwrap()).unwrap();
+        assert_eq!(file.read_next_chunk().unwrap(), b"Read only");
     }
 
     #[test]
@@ -150,7 +257,7 @@ mod tests {
         let temp_file = create_test_file(test_data);
 
         let mut file =
-            CompressedTrainingDataFile::new(temp_file.path().to_str().unwrap(), false).unwrap();
+            CompressedTrainingDataFile::open(temp_file.path().to_str().unwrap()).unwrap();
         assert!(file.has_next_chunk());
 
         let chunk = file.read_next_chunk().unwrap();
@@ -164,7 +271,7 @@ mod tests {
         let temp_file = create_test_file(test_data);
 
         let mut file =
-            CompressedTrainingDataFile::new(temp_file.path().to_str().unwrap(), false).unwrap();
+            CompressedTrainingDataFile::open(temp_file.path().to_str().unwrap()).unwrap();
         assert!(file.has_next_chunk());
 
         let _ = file.read_next_chunk().unwrap();
@@ -177,7 +284,7 @@ mod tests {
         temp_file.write_all(b"INVALID").unwrap();
 
         let mut file =
-            CompressedTrainingDataFile::new(temp_file.path().to_str().unwrap(), false).unwrap();
+            CompressedTrainingDataFile::open(temp_file.path().to_str().unwrap()).unwrap();
         match file.read_next_chunk() {
             Err(BinpackError::InvalidMagic) => (),
             _ => panic!("Expected InvalidMagic error"),
@@ -193,13 +300,127 @@ mod tests {
         temp_file.write_all(&header).unwrap();
 
         let mut file =
-            CompressedTrainingDataFile::new(temp_file.path().to_str().unwrap(), false).unwrap();
+            CompressedTrainingDataFile::open(temp_file.path().to_str().unwrap()).unwrap();
         match file.read_next_chunk() {
             Err(BinpackError::InvalidFormat(_)) => (),
             _ => panic!("Expected InvalidFormat error"),
         }
     }
 
+    #[test]
+    fn test_append_chunks() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let path = temp_file.path().to_str().unwrap();
+
+        let mut file = CompressedTrainingDataFile::create(path, false).unwrap();
+        file.append(b"Chunk1").unwrap();
+        file.append(b"Chunk2").unwrap();
+        file.flush().unwrap();
+
+        let mut file = CompressedTrainingDataFile::open(path).unwrap();
+        assert_eq!(file.read_next_chunk().unwrap(), b"Chunk1");
+        assert_eq!(file.read_next_chunk().unwrap(), b"Chunk2");
+        assert!(!file.has_next_chunk());
+    }
+
+    #[test]
+    fn test_append_to_existing_file() {
+        let temp_file = create_test_file(b"Existing");
+        let path = temp_file.path().to_str().unwrap();
+
+        let mut file = CompressedTrainingDataFile::create(path, true).unwrap();
+        file.append(b"Appended").unwrap();
+        file.flush().unwrap();
+
+        let mut file = CompressedTrainingDataFile::open(path).unwrap();
+        assert_eq!(file.read_next_chunk().unwrap(), b"Existing");
+        assert_eq!(file.read_next_chunk().unwrap(), b"Appended");
+        assert!(!file.has_next_chunk());
+    }
+
+    #[test]
+    fn test_create_truncates_existing_file() {
+        let temp_file = create_test_file(b"Existing");
+        let path = temp_file.path().to_str().unwrap();
+
+        let mut file = CompressedTrainingDataFile::create(path, false).unwrap();
+        file.append(b"New").unwrap();
+        file.flush().unwrap();
+
+        let mut file = CompressedTrainingDataFile::open(path).unwrap();
+        assert_eq!(file.read_next_chunk().unwrap(), b"New");
+        assert!(!file.has_next_chunk());
+    }
+
+    #[test]
+    fn test_append_chunk_too_large() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let path = temp_file.path().to_str().unwrap();
+
+        let mut file = CompressedTrainingDataFile::create(path, false).unwrap();
+        let data = vec![0u8; MAX_CHUNK_SIZE as usize + 1];
+
+        match file.append(&data) {
+            Err(BinpackError::InvalidFormat(_)) => (),
+            _ => panic!("Expected InvalidFormat error"),
+        }
+
+        assert_eq!(std::fs::metadata(path).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_read_from_stream() {
+        let mut data = Vec::new();
+        let mut writer = CompressedTrainingDataFile::from_stream(&mut data);
+        writer.append(b"Chunk1").unwrap();
+        writer.append(b"").unwrap();
+        writer.append(b"Chunk3").unwrap();
+
+        let mut file = CompressedTrainingDataFile::from_stream(data.as_slice());
+
+        assert!(file.has_next_chunk());
+        assert!(file.has_next_chunk());
+        assert_eq!(file.read_next_chunk().unwrap(), b"Chunk1");
+        assert_eq!(file.read_next_chunk().unwrap(), b"");
+        assert_eq!(file.read_next_chunk().unwrap(), b"Chunk3");
+        assert!(!file.has_next_chunk());
+        assert_eq!(file.read_bytes(), data.len() as u64);
+    }
+
+    #[test]
+    fn test_truncated_header_in_stream() {
+        let mut file = CompressedTrainingDataFile::from_stream(&b"BINP\x01"[..]);
+
+        assert!(file.has_next_chunk());
+        match file.read_next_chunk() {
+            Err(BinpackError::InvalidMagic) => (),
+            _ => panic!("Expected InvalidMagic error"),
+        }
+    }
+
+    #[test]
+    fn test_seek_to_chunk_in_stream() {
+        let mut data = b"garbage".to_vec();
+        let mut writer = CompressedTrainingDataFile::from_stream(&mut data);
+        writer.append(b"Chunk1").unwrap();
+        writer.append(b"Chunk2").unwrap();
+
+        let mut cursor = io::Cursor::new(data);
+        cursor.set_position(7);
+
+        let mut file = CompressedTrainingDataFile::from_stream(cursor);
+        assert_eq!(file.read_next_chunk().unwrap(), b"Chunk1");
+        assert!(file.has_next_chunk());
+
+        file.seek_to(0).unwrap();
+        assert_eq!(file.read_next_chunk().unwrap(), b"Chunk1");
+
+        file.seek_to(14).unwrap();
+        assert_eq!(file.read_bytes(), 14);
+        assert_eq!(file.read_next_chunk().unwrap(), b"Chunk2");
+        assert!(!file.has_next_chunk());
+    }
+
     #[test]
     fn test_multiple_chunks() {
         let mut temp_file = NamedTempFile::new().unwrap();
@@ -216,7 +437,7 @@ mod tests {
         temp_file.flush().unwrap();
 
         let mut file =
-            CompressedTrainingDataFile::new(temp_file.path().to_str().unwrap(), false).unwrap();
+            CompressedTrainingDataFile::open(temp_file.path().to_str().unwrap()).unwrap();
 
         for expected_chunk in chunks {
             assert!(file.has_next_chunk());
@@ -11,6 +11,11 @@ impl BitWriter {
         }
     }
 
+    pub fn clear(&mut self) {
+        self.movetext.clear();
+        self.bits_left = 0;
+    }
+
     pub fn add_bits_le8(&mut self, bits: u8, count: usize) {
         if count == 0 {
             return;
@@ -1,2 +1,3 @@
 mod bitwriter;
 pub mod move_score_list_writer;
+pub mod training_data_writer;
@@ -1,4 +1,4 @@
-use crate::arithmetic::used_bits_safe;
+use crate::arithmetic::{signed_to_unsigned, used_bits_safe};
 use crate::binpack_error::Result;
 use crate::chess::attacks::Attacks;
 use crate::chess::bitboard::Bitboard;
@@ -34,10 +34,14 @@ impl PackedMoveScoreList {
 
     pub fn clear(&mut self, initial_score: i16) {
         self.num_plies = 0;
-        self.writer.movetext.clear();
+        self.writer.clear();
         self.last_score = -initial_score;
     }
 
+    pub fn movetext(&self) -> &[u8] {
+        &self.writer.movetext
+    }
+
     pub fn add_move_score(&mut self, pos: &Position, move_: Move, score: i16) -> Result<()> {
         const SCORE_VLE_BLOCK_SIZE: usize = 4;
 
@@ -60,7 +64,7 @@ impl PackedMoveScoreList {
             .add_bits_le8(move_id as u8, used_bits_safe(num_moves as u64));
 
         // Encode the score
-        let score_delta = signed_to_unsigned(score - self.last_score);
+        let score_delta = signed_to_unsigned(score.wrapping_sub(self.last_score));
         self.writer
             .add_bits_vle16(score_delta, SCORE_VLE_BLOCK_SIZE);
         self.last_score = -score;
@@ -156,14 +160,16 @@ impl PackedMoveScoreList {
                     let long_castling_rights =
                         CastlingTraits::castling_rights(side_to_move, CastleType::Long);
 
-                    move_id = attacks_size - 1;
+                    // wraps around when the king has no other moves,
+                    // the increments below bring it back into range
+                    move_id = attacks_size.wrapping_sub(1);
 
                     if castling_rights.contains(long_castling_rights) {
-                        move_id += 1;
+                        move_id = move_id.wrapping_add(1);
                     }
 
                     if move_.castle_type() == CastleType::Short {
-                        move_id += 1;
+                        move_id = move_id.wrapping_add(1);
                     }
                 } else {
                     move_id = (attacks & before(move_.to())).count();
@@ -186,14 +192,6 @@ impl PackedMoveScoreList {
 
 // Helper functions
 
-fn signed_to_unsigned(a: i16) -> u16 {
-    let mut r = a as u16;
-    if r & 0x8000 != 0 {
-        r ^= 0x7FFF;
-    }
-    r.rotate_left(1)
-}
-
-fn before(_sq: Square) -> Bitboard {
-    todo!()
+fn before(sq: Square) -> Bitboard {
+    Bitboard::from_before(sq.index())
 }
@@ -0,0 +1,213 @@
+use crate::{
+    binpack_error::{BinpackError, Result},
+    training_data_entry::{PackedTrainingDataEntry, TrainingDataEntry, MAX_PLY},
+    training_data_file::CompressedTrainingDataFile,
+};
+
+use super::move_score_list_writer::PackedMoveScoreList;
+
+const SUGGESTED_CHUNK_SIZE: usize = 1024 * 1024;
+const MAX_MOVELIST_SIZE: usize = 10 * 1024;
+
+pub struct CompressedTrainingDataEntryWriter {
+    output_file: CompressedTrainingDataFile,
+    last_entry: Option<TrainingDataEntry>,
+    movelist: PackedMoveScoreList,
+    packed_entries: Vec<u8>,
+}
+
+impl CompressedTrainingDataEntryWriter {
+    pub fn new(path: &str, append: bool) -> Result<Self> {
+        Ok(Self {
+            output_file: CompressedTrainingDataFile::create(path, append)?,
+            last_entry: None,
+            movelist: PackedMoveScoreList::new(),
+            packed_entries: Vec::with_capacity(SUGGESTED_CHUNK_SIZE + MAX_MOVELIST_SIZE),
+        })
+    }
+
+    /// Returns [`BinpackError::InvalidEntry`] if the ply or the result can't
+    /// be stored in a binpack, nothing is written in that case
+    pub fn write_entry(&mut self, entry: &TrainingDataEntry) -> Result<()> {
+        if entry.ply > MAX_PLY {
+            return Err(BinpackError::InvalidEntry(format!(
+                "Ply {} is larger than {MAX_PLY}",
+                entry.ply
+            )));
+        }
+
+        if !(-1..=1).contains(&entry.result) {
+            return Err(BinpackError::InvalidEntry(format!(
+                "Result {} is not -1, 0 or 1",
+                entry.result
+            )));
+        }
+
+        let is_continuation = self
+            .last_entry
+            .is_some_and(|last| last.is_continuation(entry));
+
+        if is_continuation {
+            self.movelist
+                .add_move_score(&entry.pos, entry.mv, entry.score)?;
+        } else {
+            if self.last_entry.is_some() {
+                self.write_movelist();
+            }
+
+            if self.packed_entries.len() >= SUGGESTED_CHUNK_SIZE {
+                self.output_file.append(&self.packed_entries)?;
+                self.packed_entries.clear();
+            }
+
+            let packed = PackedTrainingDataEntry::pack_entry(entry);
+            self.packed_entries.extend_from_slice(&packed.data);
+
+            self.movelist.clear(entry.score);
+        }
+
+        self.last_entry = Some(*entry);
+
+        Ok(())
+    }
+
+    /// Write all buffered entries to the file and sync it to disk,
+    /// the next entry will start a new chain
+    pub fn flush(&mut self) -> Result<()> {
+        if self.last_entry.is_some() {
+            self.write_movelist();
+            self.last_entry = None;
+        }
+
+        if !self.packed_entries.is_empty() {
+            self.output_file.append(&self.packed_entries)?;
+            self.packed_entries.clear();
+        }
+
+        self.output_file.flush()?;
+
+        Ok(())
+    }
+
+    fn write_movelist(&mut self) {
+        self.packed_entries
+            .extend_from_slice(&self.movelist.num_plies.to_be_bytes());
+
+        if self.movelist.num_plies > 0 {
+            self.packed_entries
+                .extend_from_slice(self.movelist.movetext());
+        }
+    }
+}
+
+impl Drop for CompressedTrainingDataEntryWriter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::training_data_reader::CompressedTrainingDataEntryReader;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_writer_roundtrip() {
+        let reader = CompressedTrainingDataEntryReader::new("./test/ep1.binpack").unwrap();
+        let entries = reader.map(|e| e.unwrap()).collect::<Vec<_>>();
+
+        let temp_file = NamedTempFile::new().unwrap();
+        let path = temp_file.path().to_str().unwrap();
+
+        {
+            let mut writer = CompressedTrainingDataEntryWriter::new(path, false).unwrap();
+            for entry in &entries {
+                writer.write_entry(entry).unwrap();
+            }
+        }
+
+        let expected = std::fs::read("./test/ep1.binpack").unwrap();
+        let written = std::fs::read(path).unwrap();
+        assert_eq!(written, expected);
+    }
+
+    #[test]
+    fn test_writer_flush_and_append() {
+        let reader = CompressedTrainingDataEntryReader::new("./test/ep1.binpack").unwrap();
+        let entries = reader.map(|e| e.unwrap()).collect::<Vec<_>>();
+
+        let temp_file = NamedTempFile::new().unwrap();
+        let path = temp_file.path().to_str().unwrap();
+
+        let mut writer = CompressedTrainingDataEntryWriter::new(path, false).unwrap();
+        writer.write_entry(&entries[0]).unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+
+        let mut writer = CompressedTrainingDataEntryWriter::new(path, true).unwrap();
+        for entry in &entries[1..] {
+            writer.write_entry(entry).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let reader = CompressedTrainingDataEntryReader::new(path).unwrap();
+        let read = reader.map(|e| e.unwrap()).collect::<Vec<_>>();
+
+        assert_eq!(read, entries);
+    }
+
+    #[test]
+    fn test_writer_rejects_invalid_entries() {
+        let reader = CompressedTrainingDataEntryReader::new("./test/ep1.binpack").unwrap();
+        let entries = reader.map(|e| e.unwrap()).collect::<Vec<_>>();
+
+        let temp_file = NamedTempFile::new().unwrap();
+        let path = temp_file.path().to_str().unwrap();
+        let mut writer = CompressedTrainingDataEntryWriter::new(path, false).unwrap();
+
+        let mut invalid = entries[0];
+        invalid.ply = MAX_PLY + 1;
+        assert!(matches!(
+            writer.write_entry(&invalid),
+            Err(BinpackError::InvalidEntry(_))
+        ));
+
+        let mut invalid = entries[0];
+        invalid.result = 2;
+        assert!(matches!(
+            writer.write_entry(&invalid),
+            Err(BinpackError::InvalidEntry(_))
+        ));
+
+        for entry in &entries {
+            writer.write_entry(entry).unwrap();
+        }
+        drop(writer);
+
+        let expected = std::fs::read("./test/ep1.binpack").unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_writer_multiple_chains() {
+        let reader = CompressedTrainingDataEntryReader::new("./test/ep1.binpack").unwrap();
+        let entries = reader.map(|e| e.unwrap()).collect::<Vec<_>>();
+        let entries = [&entries[..], &entries[..], &entries[..]].concat();
+
+        let temp_file = NamedTempFile::new().unwrap();
+        let path = temp_file.path().to_str().unwrap();
+
+        {
+            let mut writer = CompressedTrainingDataEntryWriter::new(path, false).unwrap();
+            for entry in &entries {
+                writer.write_entry(entry).unwrap();
+            }
+        }
+
+        let reader = CompressedTrainingDataEntryReader::new(path).unwrap();
+        let read = reader.map(|e| e.unwrap()).collect::<Vec<_>>();
+
+        assert_eq!(read, entries);
+    }
+}